@@ -0,0 +1,77 @@
+// Compares the VM table against std's HashMap on the workloads the VM produces,
+// run with `cargo bench --bench table`
+
+use std::{collections::HashMap, hint::black_box, time::Instant};
+
+use loxidize::{heap::Heap, lox_value::LoxValue, object::ObjRef, table::Table};
+
+const KEYS: usize = 1_000;
+const ROUNDS: usize = 1_000;
+
+fn bench(name: &str, mut run: impl FnMut()) {
+    // Warm up caches and the allocator first
+    run();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        run();
+    }
+    let per_round = start.elapsed() / ROUNDS as u32;
+    println!("{name: <24} {per_round:>12?}");
+}
+
+fn main() {
+    let mut heap = Heap::new();
+    let keys: Vec<ObjRef> = (0..KEYS).map(|i| heap.intern(&format!("k{i}"))).collect();
+
+    bench("table insert", || {
+        let mut table = Table::new();
+        for key in &keys {
+            table.insert(*key, LoxValue::Nil);
+        }
+        black_box(table);
+    });
+    bench("hashmap insert", || {
+        let mut map = HashMap::new();
+        for key in &keys {
+            map.insert(*key, LoxValue::Nil);
+        }
+        black_box(map);
+    });
+
+    let mut table = Table::new();
+    let mut map = HashMap::new();
+    for key in &keys {
+        table.insert(*key, LoxValue::Nil);
+        map.insert(*key, LoxValue::Nil);
+    }
+
+    bench("table get", || {
+        for key in &keys {
+            black_box(table.get(*key));
+        }
+    });
+    bench("hashmap get", || {
+        for key in &keys {
+            black_box(map.get(key));
+        }
+    });
+
+    bench("table remove/insert", || {
+        for key in &keys {
+            table.remove(*key);
+            table.insert(*key, LoxValue::Nil);
+        }
+    });
+    bench("hashmap remove/insert", || {
+        for key in &keys {
+            map.remove(key);
+            map.insert(*key, LoxValue::Nil);
+        }
+    });
+
+    bench("intern existing", || {
+        for i in 0..KEYS {
+            black_box(heap.intern(&format!("k{i}")));
+        }
+    });
+}
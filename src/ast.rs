@@ -6,52 +6,83 @@ Note: Look into Zig's parser more.
 
 use std::fmt::Debug;
 
-use crate::token::Token;
+use crate::token::{Span, Token};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 
 pub struct Lit {
     pub kind: LitKind,
-    // Number for now
-    pub symbol: f64,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum LitKind {
-    Number,
+    Number(f64),
+    Bool(bool),
+    Nil,
+    // Contents without the surrounding quotes
+    Str(String),
 }
 
 impl From<f64> for Lit {
     fn from(value: f64) -> Self {
         Self {
-            kind: LitKind::Number,
-            symbol: value,
+            kind: LitKind::Number(value),
+        }
+    }
+}
+
+impl From<bool> for Lit {
+    fn from(value: bool) -> Self {
+        Self {
+            kind: LitKind::Bool(value),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Ident {
+    pub name: String,
+    // Kept for error reporting after parsing
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct Expr {
     pub kind: ExprKind,
+    pub span: Span,
 }
 
+// Ordered from lowest to highest binding power
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub enum Precedence {
     None,
-    Summation,
-    Multiplication,
-    Division,
+    Assignment, // =
+    Or,         // or
+    And,        // and
+    Equality,   // == !=
+    Comparison, // < > <= >=
+    Term,       // + -
+    Factor,     // * /
+    Unary,      // ! -
+    Call,       // . ()
+    Primary,
 }
 
 impl Precedence {
     #[must_use]
     pub fn from_token(token: &Token) -> Precedence {
         match token {
-            Token::EOF => Precedence::None,
-            Token::Plus | Token::Minus => Precedence::Summation,
-            Token::Star => Precedence::Multiplication,
-            Token::Slash => Precedence::Division,
-            default => unimplemented!("Illegal Token {:?}", default),
+            Token::Or => Precedence::Or,
+            Token::And => Precedence::And,
+            Token::EqualEqual | Token::BangEqual => Precedence::Equality,
+            Token::Greater | Token::GreaterEqual | Token::Less | Token::LessEqual => {
+                Precedence::Comparison
+            }
+            Token::Plus | Token::Minus => Precedence::Term,
+            Token::Star | Token::Slash => Precedence::Factor,
+            Token::LParen | Token::Dot => Precedence::Call,
+            // Any other token ends the expression, the parser reports it if it is out of place
+            _ => Precedence::None,
         }
     }
 }
@@ -61,7 +92,29 @@ pub type BinOp = BinOpKind;
 #[derive(Debug, Clone)]
 pub enum ExprKind {
     Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    // Kept in the tree so a parenthesized expression is never an assignment target
+    Paren(Box<Expr>),
     Lit(Lit),
+    Variable(Ident),
+    Assign(Ident, Box<Expr>),
+    // Callee and arguments
+    Call(Box<Expr>, Vec<Expr>),
+    // Property access on an instance
+    Get(Box<Expr>, Ident),
+    Set(Box<Expr>, Ident, Box<Expr>),
+    // Resolved like a local variable named `this`
+    This(Ident),
+    // The `super` keyword and the name of the method accessed on the superclass
+    Super(Ident, Ident),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnOp {
+    // `-`
+    Neg,
+    // `!`
+    Not,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -71,9 +124,58 @@ pub enum BinOpKind {
     Sub,
     Mul,
     Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // Short-circuiting, only evaluates the right operand when needed
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    Expr(Expr),
+    Print(Expr),
+    Var(Ident, Option<Expr>),
+    Block(Vec<Stmt>),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    // Initializer, condition, increment and body, lowered to jumps by the bytecode compiler
+    For(Option<Box<Stmt>>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    Fun(FunDecl),
+    Class(ClassDecl),
+    // The keyword, where misplaced returns are reported, and the returned value
+    Return(Span, Option<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassDecl {
+    pub name: Ident,
+    pub superclass: Option<Ident>,
+    pub methods: Vec<FunDecl>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunDecl {
+    pub name: Ident,
+    pub params: Vec<Ident>,
+    pub body: Vec<Stmt>,
+    // The closing brace, where the implicit return is located
+    pub end: Span,
 }
 
 #[derive(Debug)]
 pub struct Ast {
-    pub root: Expr,
+    pub stmts: Vec<Stmt>,
+    // The implicit return of the script is located at the end of the input
+    pub end: Span,
 }
@@ -1,8 +1,8 @@
-use std::{pin::Pin, ptr::NonNull};
+use std::{mem, pin::Pin, ptr::NonNull};
 
 use num_enum::TryFromPrimitive;
 
-use crate::{lox_value::LoxValue, opcodes::Op};
+use crate::{lox_value::LoxValue, object::ObjRef, opcodes::Op, token::Span};
 
 #[derive(Debug)]
 #[repr(transparent)]
@@ -11,6 +11,8 @@ pub struct Ip {
 }
 
 impl Ip {
+    /// # Safety
+    /// The pointer is only valid as long as `code` is neither moved, grown nor dropped.
     pub unsafe fn create(code: Pin<&[u8]>) -> Ip {
         let ptr = code.as_ptr();
         assert!(!ptr.is_null());
@@ -33,17 +35,85 @@ impl Ip {
         unsafe { *self.ptr.as_ptr() }
     }
 
+    #[inline]
+    pub fn get_u16(&self) -> u16 {
+        let (high, low) = unsafe { (*self.ptr.as_ptr(), *self.ptr.as_ptr().add(1)) };
+        u16::from_be_bytes([high, low])
+    }
+
     #[inline]
     pub fn inc(&mut self, offset: usize) {
         unsafe { self.ptr = self.ptr.add(offset) };
     }
+
+    #[inline]
+    pub fn dec(&mut self, offset: usize) {
+        unsafe { self.ptr = self.ptr.sub(offset) };
+    }
+}
+
+/// Position in the source an instruction was compiled from, lines and columns start at 1
+/// `start` and `end` are the byte range of the expression or statement it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Location {
+    pub line: u32,
+    pub column: u32,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl From<Span> for Location {
+    // Source code is guaranteed to be shorter than u32::MAX bytes
+    #[allow(clippy::cast_possible_truncation)]
+    fn from(span: Span) -> Self {
+        Location {
+            line: span.line as u32,
+            column: span.column as u32,
+            start: span.start as u32,
+            end: span.end as u32,
+        }
+    }
+}
+
+impl From<Location> for Span {
+    fn from(location: Location) -> Self {
+        Span {
+            start: location.start as usize,
+            end: location.end as usize,
+            line: location.line as usize,
+            column: location.column as usize,
+        }
+    }
+}
+
+// Consecutive bytes of code compiled from the same line
+#[derive(Debug, Clone, Copy)]
+struct LineRun {
+    // Offset of the first byte of the run
+    start: u32,
+    line: u32,
 }
 
+// Line runs between two checkpoints into the positions
+const RUNS_PER_CHECKPOINT: usize = 8;
+
 #[derive(Debug, Default)]
 pub struct Bytecode {
     code: Vec<u8>,
     constants: Vec<LoxValue>,
-    lines: Vec<i32>,
+    // Run-length encoded, ordered by offset
+    // Instructions and their operands share a line, so runs are much shorter than the code
+    lines: Vec<LineRun>,
+    // Every change of the location within a line, LEB128 encoded as the number of bytes since
+    // the previous change, the column, the start of the span relative to the previous one and
+    // the length of the span
+    // A line run starts with a change of zero bytes, so runs can be found by counting those
+    // Positions are only needed to report errors, so they are decoded from the last checkpoint
+    positions: Vec<u8>,
+    // Position of every RUNS_PER_CHECKPOINT-th line run, spans start over from zero there
+    checkpoints: Vec<u32>,
+    // Offset and location of the last change, to encode the next one
+    last_location: (usize, Location),
     pub finished_compilation: bool,
 }
 
@@ -53,10 +123,21 @@ impl Bytecode {
             code: vec![],
             constants: vec![],
             lines: vec![],
+            positions: vec![],
+            checkpoints: vec![],
+            last_location: (0, Location::default()),
             finished_compilation: false,
         }
     }
 
+    // The location tables are complete, so their spare capacity is released
+    pub fn finish_compilation(&mut self) {
+        self.lines.shrink_to_fit();
+        self.positions.shrink_to_fit();
+        self.checkpoints.shrink_to_fit();
+        self.finished_compilation = true;
+    }
+
     /*
     When this is called compilation should have finished, therefore code should have stopped growing
     With a secure memory location this pointer is safe, as the interpreter is single-threaded
@@ -64,7 +145,7 @@ impl Bytecode {
     */
     pub fn get_base_ip(&self) -> Option<Ip> {
         if self.finished_compilation {
-            return unsafe { Some(Ip::create(Pin::new(self.code.as_slice()))) };
+            unsafe { Some(Ip::create(Pin::new(self.code.as_slice()))) }
         } else {
             None
         }
@@ -74,9 +155,118 @@ impl Bytecode {
         self.code.len()
     }
 
-    pub fn write_u8(&mut self, byte: u8, line: i32) {
+    // Offset of the instruction pointer into the code of this block
+    pub fn get_offset(&self, ip: &Ip) -> usize {
+        ip.ptr.as_ptr() as usize - self.code.as_ptr() as usize
+    }
+
+    pub fn line_for_offset(&self, offset: usize) -> u32 {
+        self.lines[self.run_index(offset)].line
+    }
+
+    pub fn location_for_offset(&self, offset: usize) -> Location {
+        let index = self.run_index(offset);
+        let run = self.lines[index];
+
+        // Decodes from the checkpoint up to the first change of the run
+        let mut position = self.checkpoints[index / RUNS_PER_CHECKPOINT] as usize;
+        let mut runs = index % RUNS_PER_CHECKPOINT + 1;
+        let mut location = Location::default();
+        while runs > 0 {
+            let (len, next) = self.read_change(&mut position, location);
+            if len == 0 {
+                runs -= 1;
+            }
+            location = next;
+        }
+
+        let mut start = run.start as usize;
+        while position < self.positions.len() {
+            let (len, next) = self.read_change(&mut position, location);
+            // Either the next run or a later change
+            if len == 0 || start + len > offset {
+                break;
+            }
+            start += len;
+            location = next;
+        }
+        Location {
+            line: run.line,
+            ..location
+        }
+    }
+
+    // Returns the number of bytes since the previous change, along with the changed location
+    // The line is kept by the line runs, so it is left as is
+    // Spans are within the source, so their start is never negative nor beyond u32::MAX
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn read_change(&self, position: &mut usize, previous: Location) -> (usize, Location) {
+        let len = read_leb128(&self.positions, position) as usize;
+        let column = read_leb128(&self.positions, position) as u32;
+        let delta = zigzag_decode(read_leb128(&self.positions, position));
+        let start = (i64::from(previous.start) + delta) as u32;
+        let end = start + read_leb128(&self.positions, position) as u32;
+        let location = Location {
+            column,
+            start,
+            end,
+            ..previous
+        };
+        (len, location)
+    }
+
+    fn write_change(&mut self, len: usize, location: Location, previous: Location) {
+        let delta = i64::from(location.start) - i64::from(previous.start);
+        write_leb128(&mut self.positions, len as u64);
+        write_leb128(&mut self.positions, location.column.into());
+        write_leb128(&mut self.positions, zigzag_encode(delta));
+        write_leb128(&mut self.positions, (location.end - location.start).into());
+    }
+
+    // Binary search for the run containing the offset
+    fn run_index(&self, offset: usize) -> usize {
+        assert!(
+            offset < self.code.len(),
+            "Offset {offset} is outside of the code"
+        );
+        // The first run starts at offset zero, so at least one run starts before the offset
+        self.lines
+            .partition_point(|run| run.start as usize <= offset)
+            - 1
+    }
+
+    // Code is guaranteed to be shorter than u32::MAX bytes, like the source it is compiled from
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn write_u8(&mut self, byte: u8, location: Location) {
+        let offset = self.code.len();
+        let (last_offset, last) = self.last_location;
+        if self
+            .lines
+            .last()
+            .is_none_or(|run| run.line != location.line)
+        {
+            let previous = if self.lines.len().is_multiple_of(RUNS_PER_CHECKPOINT) {
+                self.checkpoints.push(self.positions.len() as u32);
+                Location::default()
+            } else {
+                last
+            };
+            self.lines.push(LineRun {
+                start: offset as u32,
+                line: location.line,
+            });
+            self.write_change(0, location, previous);
+            self.last_location = (offset, location);
+        } else if last != location {
+            self.write_change(offset - last_offset, location, last);
+            self.last_location = (offset, location);
+        }
         self.code.push(byte);
-        self.lines.push(line);
+    }
+
+    // Used for backpatching jumps once their target is known
+    pub fn patch_u8(&mut self, offset: usize, byte: u8) {
+        self.code[offset] = byte;
     }
 
     pub fn add_constant(&mut self, value: LoxValue) -> usize {
@@ -84,8 +274,39 @@ impl Bytecode {
         self.constants.len() - 1
     }
 
+    pub fn constants(&self) -> &[LoxValue] {
+        &self.constants
+    }
+
+    // Memory owned by the block, used by the heap to decide when to collect garbage
+    pub fn allocated_bytes(&self) -> usize {
+        self.code.capacity()
+            + self.constants.capacity() * mem::size_of::<LoxValue>()
+            + self.location_bytes()
+    }
+
+    /// Memory used to locate the instructions in the source
+    pub fn location_bytes(&self) -> usize {
+        self.lines.capacity() * mem::size_of::<LineRun>()
+            + self.positions.capacity()
+            + self.checkpoints.capacity() * mem::size_of::<u32>()
+    }
+
     pub fn get_constant(&self, index: usize) -> LoxValue {
-        self.constants.get(index).unwrap().clone()
+        *self.constants.get(index).unwrap()
+    }
+
+    // Names are interned strings, so a repeated identifier reuses its constant
+    pub fn add_identifier(&mut self, name: ObjRef) -> usize {
+        let value = LoxValue::Obj(name);
+        if let Some(index) = self
+            .constants
+            .iter()
+            .position(|constant| *constant == value)
+        {
+            return index;
+        }
+        self.add_constant(value)
     }
 
     pub fn disassemble(&self, name: &str) -> String {
@@ -97,10 +318,10 @@ impl Bytecode {
         while op_index < self.code.len() {
             disassembly.push_str(&format!("{op_index:04} "));
 
-            if op_index > 0 && self.lines[op_index] == self.lines[op_index - 1] {
+            let line = self.line_for_offset(op_index);
+            if op_index > 0 && line == self.line_for_offset(op_index - 1) {
                 disassembly.push_str("   | ");
             } else {
-                let line = self.lines[op_index];
                 disassembly.push_str(&format!("{line: >4} "));
             }
 
@@ -111,7 +332,81 @@ impl Bytecode {
                         let value = &self.constants[constant as usize];
                         format!("{op: <16} {constant:04} {value}")
                     }
-                    Op::Ret | Op::Negate | Op::Add | Op::Subtract | Op::Multiply | Op::Divide => {
+                    Op::GetLocal | Op::SetLocal | Op::GetUpvalue | Op::SetUpvalue => {
+                        let slot = self.code[op_index + 1];
+                        format!("{op: <16} {slot:04}")
+                    }
+                    Op::Closure => {
+                        let constant = self.code[op_index + 1];
+                        let value = &self.constants[constant as usize];
+                        let mut text = format!("{op: <16} {constant:04} {value}");
+
+                        let upvalue_count = value.as_function().map_or(0, |f| f.upvalue_count);
+                        for _ in 0..upvalue_count {
+                            let operand_index = op_index + 1 + op.operand_count();
+                            let kind = match self.code[operand_index] {
+                                0 => "upvalue",
+                                _ => "local",
+                            };
+                            let index = self.code[operand_index + 1];
+                            text.push_str(&format!(
+                                "\n{operand_index:04}    |                      {kind} {index}"
+                            ));
+                            op_index += 2;
+                        }
+                        text
+                    }
+                    Op::Call => {
+                        let arg_count = self.code[op_index + 1];
+                        format!("{op: <16} {arg_count:04}")
+                    }
+                    Op::Jump | Op::JumpIfFalse | Op::Loop => {
+                        let jump =
+                            u16::from_be_bytes([self.code[op_index + 1], self.code[op_index + 2]])
+                                as usize;
+                        let next = op_index + 1 + op.operand_count();
+                        let target = if matches!(op, Op::Loop) {
+                            next - jump
+                        } else {
+                            next + jump
+                        };
+                        format!("{op: <16} {op_index:04} -> {target:04}")
+                    }
+                    Op::Invoke | Op::SuperInvoke => {
+                        let constant = self.code[op_index + 1];
+                        let arg_count = self.code[op_index + 2];
+                        let name = &self.constants[constant as usize];
+                        format!("{op: <16} ({arg_count} args) {constant:04} '{name}'")
+                    }
+                    Op::DefineGlobal
+                    | Op::GetGlobal
+                    | Op::SetGlobal
+                    | Op::GetProperty
+                    | Op::SetProperty
+                    | Op::GetSuper
+                    | Op::Class
+                    | Op::Method => {
+                        let constant = self.code[op_index + 1];
+                        let name = &self.constants[constant as usize];
+                        format!("{op: <16} {constant:04} '{name}'")
+                    }
+                    Op::Return
+                    | Op::CloseUpvalue
+                    | Op::Inherit
+                    | Op::Nil
+                    | Op::True
+                    | Op::False
+                    | Op::Pop
+                    | Op::Print
+                    | Op::Negate
+                    | Op::Add
+                    | Op::Subtract
+                    | Op::Multiply
+                    | Op::Divide
+                    | Op::Equal
+                    | Op::Greater
+                    | Op::Less
+                    | Op::Not => {
                         format!("{op}")
                     }
                 };
@@ -125,6 +420,47 @@ impl Bytecode {
             disassembly.push('\n');
         }
 
+        // Functions declared in this block are only reachable through its constants
+        for constant in &self.constants {
+            if let Some(function) = constant.as_function() {
+                disassembly.push_str(&function.bytecode.disassemble(&function.to_string()));
+            }
+        }
+
         disassembly
     }
 }
+
+// Seven bits at a time, the high bit is set on every byte but the last
+fn write_leb128(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_leb128(bytes: &[u8], position: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// Interleaves negative and positive numbers, so small ones of either sign take few bytes
+#[allow(clippy::cast_sign_loss)]
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn zigzag_decode(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
@@ -1,57 +1,845 @@
+use std::mem;
+
 use crate::{
-    ast::{Ast, BinOpKind, ExprKind},
-    bytecode::Bytecode,
+    ast::{
+        Ast, BinOpKind, ClassDecl, Expr, ExprKind, FunDecl, Ident, LitKind, Stmt, StmtKind, UnOp,
+    },
+    bytecode::{Bytecode, Location},
+    diagnostics::{Code, Diagnostic},
+    heap::Heap,
+    lox_value::LoxValue,
+    object::{ObjFunction, ObjKind, ObjRef},
     opcodes::Op,
+    token::Span,
 };
 
+// Slots and upvalues are addressed by a single byte operand
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+
+struct Local<'ast> {
+    name: &'ast str,
+    // None while the initializer of the variable is being compiled
+    depth: Option<usize>,
+    // Captured locals are moved off the stack when they go out of scope
+    is_captured: bool,
+}
+
+// A variable captured by a function, either a local of the directly enclosing function or one
+// of its upvalues
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+// A class declaration enclosing the code being compiled
+struct ClassState {
+    has_superclass: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    // Implicitly returns `this`, returning any other value is an error
+    Initializer,
+}
+
+// A function whose body is being compiled, every nested declaration pushes its own
+struct FunctionState<'ast> {
+    kind: FunctionKind,
+    // Interned when compilation of the function starts, so the collector can see it
+    name: Option<ObjRef>,
+    bytecode: Bytecode,
+    // Mirrors the stack slots the locals will occupy at runtime
+    locals: Vec<Local<'ast>>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    // Limits of the bytecode format already exceeded, every node after the first one exceeds
+    // them as well so each limit is only reported once
+    exceeded_limits: Vec<Code>,
+}
+
+impl FunctionState<'_> {
+    fn new(kind: FunctionKind, name: Option<ObjRef>) -> Self {
+        Self {
+            kind,
+            name,
+            bytecode: Bytecode::default(),
+            // Slot zero holds the called function itself and can't be named, in methods it
+            // holds the receiver instead
+            locals: vec![Local {
+                name: match kind {
+                    FunctionKind::Method | FunctionKind::Initializer => "this",
+                    FunctionKind::Script | FunctionKind::Function => "",
+                },
+                depth: Some(0),
+                is_captured: false,
+            }],
+            upvalues: vec![],
+            scope_depth: 0,
+            exceeded_limits: vec![],
+        }
+    }
+}
+
 pub struct BytecodeCompiler<'ast> {
     ast: &'ast Ast,
-    bytecode_block: Bytecode,
+    // The innermost function is last, the top-level script first
+    functions: Vec<FunctionState<'ast>>,
+    // The innermost class is last
+    classes: Vec<ClassState>,
+    // Constants like string literals are allocated on the heap of the VM
+    heap: &'ast mut Heap,
+    // Marks the objects the owner of the heap keeps alive, as garbage may be collected while
+    // compiling
+    mark_roots: &'ast dyn Fn(&mut Heap),
+    diagnostics: Vec<Diagnostic>,
+    // Span of the node being compiled, its location is attributed to every emitted instruction
+    span: Span,
 }
 
 impl<'ast> BytecodeCompiler<'ast> {
-    pub fn new(ast: &'ast Ast) -> Self {
+    pub fn new(ast: &'ast Ast, heap: &'ast mut Heap, mark_roots: &'ast dyn Fn(&mut Heap)) -> Self {
         Self {
-            ast: ast,
-            bytecode_block: Bytecode::default(),
+            ast,
+            functions: vec![FunctionState::new(FunctionKind::Script, None)],
+            classes: vec![],
+            heap,
+            mark_roots,
+            diagnostics: vec![],
+            span: Span::default(),
+        }
+    }
+
+    pub fn compile(mut self) -> Result<Bytecode, Vec<Diagnostic>> {
+        for stmt in &self.ast.stmts {
+            self.visit_stmt(stmt);
+        }
+        self.span = self.ast.end;
+        let script = self.end_function();
+
+        if !self.diagnostics.is_empty() {
+            return Err(self.diagnostics);
+        }
+        Ok(script.bytecode)
+    }
+
+    fn function(&mut self) -> &mut FunctionState<'ast> {
+        self.functions
+            .last_mut()
+            .expect("The script is compiled as the outermost function")
+    }
+
+    fn bytecode(&mut self) -> &mut Bytecode {
+        &mut self.function().bytecode
+    }
+
+    // Functions being compiled are not on the heap yet, their constants are rooted here
+    fn collect_garbage(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+
+        (self.mark_roots)(self.heap);
+        for function in &self.functions {
+            if let Some(name) = function.name {
+                self.heap.mark_object(name);
+            }
+            for constant in function.bytecode.constants() {
+                self.heap.mark_value(*constant);
+            }
+        }
+        self.heap.collect();
+    }
+
+    fn intern(&mut self, value: &str) -> ObjRef {
+        self.collect_garbage();
+        self.heap.intern(value)
+    }
+
+    // Limits of the bytecode format exceeded by the node being compiled as a whole
+    fn limit_error(&mut self, code: Code, message: &str) {
+        self.limit_error_at(self.functions.len() - 1, self.span, code, message);
+    }
+
+    fn emit_op(&mut self, op: Op) {
+        self.emit_byte(op.into());
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let location = self.span.into();
+        self.bytecode().write_u8(byte, location);
+    }
+
+    fn error_at(&mut self, span: Span, code: Code, message: &str) {
+        self.diagnostics
+            .push(Diagnostic::error(code, message, span));
+    }
+
+    fn limit_error_at(&mut self, function: usize, span: Span, code: Code, message: &str) {
+        let exceeded = &mut self.functions[function].exceeded_limits;
+        if !exceeded.contains(&code) {
+            exceeded.push(code);
+            self.error_at(span, code, message);
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        // Nested nodes restore the span when they are done
+        let outer = mem::replace(&mut self.span, stmt.span);
+        self.compile_stmt(stmt);
+        self.span = outer;
+    }
+
+    fn compile_stmt(&mut self, stmt: &'ast Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.visit_expr(expr);
+                // Expression statements discard their value
+                self.emit_op(Op::Pop);
+            }
+            StmtKind::Print(expr) => {
+                self.visit_expr(expr);
+                self.emit_op(Op::Print);
+            }
+            StmtKind::Var(ident, init) => {
+                if self.function().scope_depth > 0 {
+                    self.declare_local(ident);
+                }
+                match init {
+                    Some(init) => self.visit_expr(init),
+                    None => self.emit_op(Op::Nil),
+                }
+                self.define_variable(ident);
+            }
+            StmtKind::Fun(decl) => {
+                if self.function().scope_depth > 0 {
+                    self.declare_local(&decl.name);
+                    // The name is usable inside the body, so local functions can recurse
+                    self.mark_initialized();
+                }
+                self.visit_function(decl, FunctionKind::Function);
+                self.define_variable(&decl.name);
+            }
+            StmtKind::Class(decl) => self.visit_class(decl),
+            StmtKind::Return(keyword, value) => {
+                let kind = self.function().kind;
+                if kind == FunctionKind::Script {
+                    self.error_at(
+                        *keyword,
+                        Code::ReturnAtTopLevel,
+                        "Can't return from top-level code.",
+                    );
+                }
+                match value {
+                    Some(value) => {
+                        if kind == FunctionKind::Initializer {
+                            self.error_at(
+                                *keyword,
+                                Code::ReturnFromInitializer,
+                                "Can't return a value from an initializer.",
+                            );
+                        }
+                        self.visit_expr(value);
+                        self.emit_op(Op::Return);
+                    }
+                    None => self.emit_return(),
+                }
+            }
+            StmtKind::Block(stmts) => {
+                self.begin_scope();
+                for stmt in stmts {
+                    self.visit_stmt(stmt);
+                }
+                self.end_scope();
+            }
+            StmtKind::If(cond, then_branch, else_branch) => {
+                self.visit_expr(cond);
+
+                let then_jump = self.emit_jump(Op::JumpIfFalse);
+                // The condition is left on the stack by the jump, so each branch pops it
+                self.emit_op(Op::Pop);
+                self.visit_stmt(then_branch);
+
+                let else_jump = self.emit_jump(Op::Jump);
+                self.patch_jump(then_jump);
+                self.emit_op(Op::Pop);
+
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            StmtKind::While(cond, body) => {
+                let loop_start = self.bytecode().get_code_len();
+                self.visit_expr(cond);
+
+                let exit_jump = self.emit_jump(Op::JumpIfFalse);
+                self.emit_op(Op::Pop);
+                self.visit_stmt(body);
+                self.emit_loop(loop_start);
+
+                self.patch_jump(exit_jump);
+                self.emit_op(Op::Pop);
+            }
+            StmtKind::For(init, cond, incr, body) => {
+                // Variables declared in the initializer are scoped to the loop
+                self.begin_scope();
+                if let Some(init) = init {
+                    self.visit_stmt(init);
+                }
+
+                let mut loop_start = self.bytecode().get_code_len();
+                let exit_jump = cond.as_ref().map(|cond| {
+                    self.visit_expr(cond);
+                    let exit_jump = self.emit_jump(Op::JumpIfFalse);
+                    self.emit_op(Op::Pop);
+                    exit_jump
+                });
+
+                // The increment is compiled before the body but runs after it
+                if let Some(incr) = incr {
+                    let body_jump = self.emit_jump(Op::Jump);
+                    let incr_start = self.bytecode().get_code_len();
+                    self.visit_expr(incr);
+                    self.emit_op(Op::Pop);
+
+                    self.emit_loop(loop_start);
+                    loop_start = incr_start;
+                    self.patch_jump(body_jump);
+                }
+
+                self.visit_stmt(body);
+                self.emit_loop(loop_start);
+
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump);
+                    self.emit_op(Op::Pop);
+                }
+                self.end_scope();
+            }
+        }
+    }
+
+    fn visit_class(&mut self, decl: &'ast ClassDecl) {
+        let name = self.identifier(&decl.name.name);
+        if self.function().scope_depth > 0 {
+            self.declare_local(&decl.name);
+        }
+        self.emit_op(Op::Class);
+        self.emit_byte(name);
+        self.define_variable(&decl.name);
+
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if let Some(superclass) = &decl.superclass {
+            if superclass.name == decl.name.name {
+                let error = Diagnostic::error(
+                    Code::InheritFromSelf,
+                    "A class can't inherit from itself.",
+                    superclass.span,
+                )
+                .with_label(decl.name.span, "the class being declared");
+                self.diagnostics.push(error);
+            }
+            self.emit_get_variable(superclass);
+
+            // The superclass is kept in a local, so methods can capture it for `super` calls
+            self.begin_scope();
+            let function = self.function();
+            function.locals.push(Local {
+                name: "super",
+                depth: Some(function.scope_depth),
+                is_captured: false,
+            });
+
+            self.emit_get_variable(&decl.name);
+            self.emit_op(Op::Inherit);
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        // Methods are attached to the class while it sits on top of the stack
+        self.emit_get_variable(&decl.name);
+
+        for method in &decl.methods {
+            let kind = if method.name.name == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.visit_function(method, kind);
+
+            let name = self.identifier(&method.name.name);
+            self.emit_op(Op::Method);
+            self.emit_byte(name);
+        }
+
+        self.emit_op(Op::Pop);
+
+        if self
+            .classes
+            .pop()
+            .is_some_and(|class| class.has_superclass)
+        {
+            self.end_scope();
+        }
+    }
+
+    // Reports uses of `super` without a superclass to resolve it in
+    fn check_super(&mut self, keyword: &Ident) {
+        match self.classes.last() {
+            None => self.error_at(
+                keyword.span,
+                Code::SuperOutsideClass,
+                "Can't use 'super' outside of a class.",
+            ),
+            Some(class) if !class.has_superclass => self.error_at(
+                keyword.span,
+                Code::SuperWithoutSuperclass,
+                "Can't use 'super' in a class with no superclass.",
+            ),
+            Some(_) => {}
+        }
+    }
+
+    fn visit_function(&mut self, decl: &'ast FunDecl, kind: FunctionKind) {
+        let name = self.intern(&decl.name.name);
+        self.functions.push(FunctionState::new(kind, Some(name)));
+        // Parameters are the first locals of the function, no scope is ended as the whole
+        // frame is discarded on return
+        self.begin_scope();
+        for param in &decl.params {
+            self.declare_local(param);
+            self.mark_initialized();
+        }
+        for stmt in &decl.body {
+            self.visit_stmt(stmt);
+        }
+        let outer = mem::replace(&mut self.span, decl.end);
+        // The finished function is only rooted by the compiler until it becomes a constant of
+        // the enclosing function, so nothing may be collected in between
+        self.collect_garbage();
+        let state = self.end_function();
+        let function = self.heap.alloc(ObjKind::Function(ObjFunction {
+            // The parser reports functions with too many parameters
+            arity: decl.params.len() as u8,
+            upvalue_count: state.upvalues.len(),
+            bytecode: state.bytecode,
+            name: state.name,
+        }));
+        self.span = outer;
+
+        // The closure instruction describes where each captured variable is found
+        let constant = self.make_constant(LoxValue::Obj(function));
+        self.emit_op(Op::Closure);
+        self.emit_byte(constant);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local.into());
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    // Returns from the current function without an explicit value
+    fn emit_return(&mut self) {
+        if self.function().kind == FunctionKind::Initializer {
+            self.emit_op(Op::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(Op::Nil);
+        }
+        self.emit_op(Op::Return);
+    }
+
+    fn end_function(&mut self) -> FunctionState<'ast> {
+        // Reaching the end of a body without a return statement returns nil
+        self.emit_return();
+
+        let mut function = self
+            .functions
+            .pop()
+            .expect("Every compiled function has a state");
+        function.bytecode.finish_compilation();
+        function
+    }
+
+    // Emits a forward jump with a placeholder offset, returns the offset to patch
+    fn emit_jump(&mut self, op: Op) -> usize {
+        self.emit_op(op);
+        self.emit_byte(u8::MAX);
+        self.emit_byte(u8::MAX);
+        self.bytecode().get_code_len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // The jump is relative to the instruction following its operand
+        let jump = self.bytecode().get_code_len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            self.limit_error(Code::TooMuchCode, "Too much code to jump over.");
+            return;
+        };
+
+        let [high, low] = jump.to_be_bytes();
+        self.bytecode().patch_u8(offset, high);
+        self.bytecode().patch_u8(offset + 1, low);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(Op::Loop);
+
+        // Also skip the operand of the loop instruction itself
+        let jump = self.bytecode().get_code_len() - loop_start + 2;
+        let jump = u16::try_from(jump).unwrap_or_else(|_| {
+            self.limit_error(Code::TooMuchCode, "Loop body too large.");
+            0
+        });
+
+        let [high, low] = jump.to_be_bytes();
+        self.emit_byte(high);
+        self.emit_byte(low);
+    }
+
+    fn begin_scope(&mut self) {
+        self.function().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let location: Location = self.span.into();
+        let function = self.function();
+        function.scope_depth -= 1;
+
+        while let Some(local) = function
+            .locals
+            .pop_if(|local| local.depth.is_none_or(|depth| depth > function.scope_depth))
+        {
+            let op = if local.is_captured {
+                Op::CloseUpvalue
+            } else {
+                Op::Pop
+            };
+            function.bytecode.write_u8(op.into(), location);
         }
     }
 
-    pub fn compile(mut self) -> Bytecode {
-        self.visit_expr(&self.ast.root);
-        // While compiling arithmetic expressions, a return must be inserted manually
-        self.bytecode_block.write_u8(Op::Ret.into(), 111);
-        self.bytecode_block.finished_compilation = true;
-        return self.bytecode_block;
+    fn declare_local(&mut self, ident: &'ast Ident) {
+        let function = self.function();
+        let shadows_same_scope = function
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= function.scope_depth))
+            .any(|local| local.name == ident.name);
+        if shadows_same_scope {
+            self.error_at(
+                ident.span,
+                Code::DuplicateLocal,
+                "Already a variable with this name in this scope.",
+            );
+        }
+
+        if self.function().locals.len() == MAX_LOCALS {
+            self.limit_error_at(
+                self.functions.len() - 1,
+                ident.span,
+                Code::TooManyLocals,
+                "Too many local variables in function.",
+            );
+            return;
+        }
+        self.function().locals.push(Local {
+            name: &ident.name,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    // The value of the initializer already sits in the slot of the latest local
+    fn mark_initialized(&mut self) {
+        let function = self.function();
+        if let Some(local) = function.locals.last_mut() {
+            local.depth = Some(function.scope_depth);
+        }
+    }
+
+    fn define_variable(&mut self, ident: &Ident) {
+        if self.function().scope_depth > 0 {
+            self.mark_initialized();
+        } else {
+            let name = self.identifier(&ident.name);
+            self.emit_op(Op::DefineGlobal);
+            self.emit_byte(name);
+        }
     }
 
-    fn visit_expr(&mut self, expr: &'ast crate::ast::Expr) -> () {
+    fn emit_get_variable(&mut self, ident: &Ident) {
+        let (op, _, operand) = self.resolve_variable(ident);
+        self.emit_op(op);
+        self.emit_byte(operand);
+    }
+
+    // Returns the get and set instructions for the variable, along with their operand
+    fn resolve_variable(&mut self, ident: &Ident) -> (Op, Op, u8) {
+        let function = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(function, ident) {
+            (Op::GetLocal, Op::SetLocal, slot)
+        } else if let Some(index) = self.resolve_upvalue(function, ident) {
+            (Op::GetUpvalue, Op::SetUpvalue, index)
+        } else {
+            let name = self.identifier(&ident.name);
+            (Op::GetGlobal, Op::SetGlobal, name)
+        }
+    }
+
+    // Locals are searched innermost first, so shadowing resolves to the latest declaration
+    fn resolve_local(&mut self, function: usize, ident: &Ident) -> Option<u8> {
+        let (slot, initialized) = self.functions[function]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == ident.name)
+            .map(|(slot, local)| (slot, local.depth.is_some()))?;
+
+        if !initialized {
+            self.error_at(
+                ident.span,
+                Code::LocalInOwnInitializer,
+                "Can't read local variable in its own initializer.",
+            );
+        }
+        Some(slot as u8)
+    }
+
+    // Looks through the enclosing functions, every function in between captures the variable
+    // as well so it can be handed down when the closures are created
+    fn resolve_upvalue(&mut self, function: usize, ident: &Ident) -> Option<u8> {
+        let enclosing = function.checked_sub(1)?;
+
+        if let Some(slot) = self.resolve_local(enclosing, ident) {
+            self.functions[enclosing].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(function, slot, true, ident));
+        }
+
+        let index = self.resolve_upvalue(enclosing, ident)?;
+        Some(self.add_upvalue(function, index, false, ident))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool, ident: &Ident) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|other| *other == upvalue) {
+            return existing as u8;
+        }
+
+        if upvalues.len() == MAX_UPVALUES {
+            self.limit_error_at(
+                function,
+                ident.span,
+                Code::TooManyUpvalues,
+                "Too many closure variables in function.",
+            );
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    // `this` is implicit in super calls, it is resolved at the location of the keyword
+    fn this_ident(keyword: &Ident) -> Ident {
+        Ident {
+            name: "this".to_owned(),
+            span: keyword.span,
+        }
+    }
+
+    fn make_constant(&mut self, value: LoxValue) -> u8 {
+        let constant = self.bytecode().add_constant(value);
+        self.constant_operand(constant)
+    }
+
+    fn emit_constant(&mut self, value: LoxValue) {
+        let constant = self.make_constant(value);
+        self.emit_op(Op::ConstantSmall);
+        self.emit_byte(constant);
+    }
+
+    fn identifier(&mut self, name: &str) -> u8 {
+        let name = self.intern(name);
+        let index = self.bytecode().add_identifier(name);
+        self.constant_operand(index)
+    }
+
+    // Constants are addressed by a single byte operand
+    fn constant_operand(&mut self, index: usize) -> u8 {
+        u8::try_from(index).unwrap_or_else(|_| {
+            self.limit_error(Code::TooManyConstants, "Too many constants in one chunk.");
+            0
+        })
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        let outer = mem::replace(&mut self.span, expr.span);
+        self.compile_expr(expr);
+        self.span = outer;
+    }
+
+    fn compile_expr(&mut self, expr: &'ast Expr) {
         match &expr.kind {
+            ExprKind::Binary(BinOpKind::And, lhs, rhs) => {
+                self.visit_expr(lhs);
+                // A falsey left operand is the result, otherwise it is discarded
+                let end_jump = self.emit_jump(Op::JumpIfFalse);
+                self.emit_op(Op::Pop);
+                self.visit_expr(rhs);
+                self.patch_jump(end_jump);
+            }
+            ExprKind::Binary(BinOpKind::Or, lhs, rhs) => {
+                self.visit_expr(lhs);
+                // A truthy left operand is the result, otherwise it is discarded
+                let else_jump = self.emit_jump(Op::JumpIfFalse);
+                let end_jump = self.emit_jump(Op::Jump);
+                self.patch_jump(else_jump);
+                self.emit_op(Op::Pop);
+                self.visit_expr(rhs);
+                self.patch_jump(end_jump);
+            }
             ExprKind::Binary(op, lhs, rhs) => {
                 self.visit_expr(lhs);
                 self.visit_expr(rhs);
 
                 match op {
                     BinOpKind::Add => {
-                        self.bytecode_block.write_u8(Op::Add.into(), 111);
+                        self.emit_op(Op::Add);
                     }
                     BinOpKind::Sub => {
-                        self.bytecode_block.write_u8(Op::Subtract.into(), 111);
+                        self.emit_op(Op::Subtract);
                     }
                     BinOpKind::Mul => {
-                        self.bytecode_block.write_u8(Op::Multiply.into(), 111);
+                        self.emit_op(Op::Multiply);
                     }
                     BinOpKind::Div => {
-                        self.bytecode_block.write_u8(Op::Divide.into(), 111);
+                        self.emit_op(Op::Divide);
+                    }
+                    BinOpKind::Eq => {
+                        self.emit_op(Op::Equal);
+                    }
+                    BinOpKind::Gt => {
+                        self.emit_op(Op::Greater);
+                    }
+                    BinOpKind::Lt => {
+                        self.emit_op(Op::Less);
+                    }
+                    // The remaining comparisons are the negations of the ones above
+                    BinOpKind::Ne => {
+                        self.emit_op(Op::Equal);
+                        self.emit_op(Op::Not);
+                    }
+                    BinOpKind::Ge => {
+                        self.emit_op(Op::Less);
+                        self.emit_op(Op::Not);
+                    }
+                    BinOpKind::Le => {
+                        self.emit_op(Op::Greater);
+                        self.emit_op(Op::Not);
+                    }
+                    BinOpKind::And | BinOpKind::Or => unreachable!("Handled above"),
+                }
+            }
+            ExprKind::Unary(op, operand) => {
+                self.visit_expr(operand);
+                match op {
+                    UnOp::Neg => self.emit_op(Op::Negate),
+                    UnOp::Not => self.emit_op(Op::Not),
+                }
+            }
+            ExprKind::Paren(inner) => self.visit_expr(inner),
+            ExprKind::Lit(lit) => match &lit.kind {
+                LitKind::Number(num) => self.emit_constant((*num).into()),
+                LitKind::Str(value) => {
+                    let string = self.intern(value);
+                    self.emit_constant(LoxValue::Obj(string));
+                }
+                LitKind::Bool(true) => self.emit_op(Op::True),
+                LitKind::Bool(false) => self.emit_op(Op::False),
+                LitKind::Nil => self.emit_op(Op::Nil),
+            },
+            ExprKind::Variable(ident) => self.emit_get_variable(ident),
+            ExprKind::Call(callee, args) => {
+                // Calling a method directly avoids allocating a bound method
+                let (op, method) = match &callee.kind {
+                    ExprKind::Get(object, name) => {
+                        self.visit_expr(object);
+                        (Op::Invoke, Some(name))
+                    }
+                    ExprKind::Super(keyword, name) => {
+                        self.check_super(keyword);
+                        self.emit_get_variable(&Self::this_ident(keyword));
+                        (Op::SuperInvoke, Some(name))
                     }
+                    _ => {
+                        self.visit_expr(callee);
+                        (Op::Call, None)
+                    }
+                };
+                for arg in args {
+                    self.visit_expr(arg);
+                }
+
+                if let ExprKind::Super(keyword, _) = &callee.kind {
+                    self.emit_get_variable(keyword);
                 }
+                self.emit_op(op);
+                if let Some(name) = method {
+                    let name = self.identifier(&name.name);
+                    self.emit_byte(name);
+                }
+                // The parser reports calls with too many arguments
+                self.emit_byte(args.len() as u8);
+            }
+            ExprKind::Get(object, name) => {
+                self.visit_expr(object);
+                let name = self.identifier(&name.name);
+                self.emit_op(Op::GetProperty);
+                self.emit_byte(name);
+            }
+            ExprKind::Set(object, name, value) => {
+                self.visit_expr(object);
+                self.visit_expr(value);
+                let name = self.identifier(&name.name);
+                self.emit_op(Op::SetProperty);
+                self.emit_byte(name);
+            }
+            ExprKind::This(ident) => {
+                if self.classes.is_empty() {
+                    self.error_at(
+                        ident.span,
+                        Code::ThisOutsideClass,
+                        "Can't use 'this' outside of a class.",
+                    );
+                    return;
+                }
+                self.emit_get_variable(ident);
+            }
+            ExprKind::Super(keyword, name) => {
+                self.check_super(keyword);
+                // The receiver is bound to the method found on the superclass
+                self.emit_get_variable(&Self::this_ident(keyword));
+                self.emit_get_variable(keyword);
+                let name = self.identifier(&name.name);
+                self.emit_op(Op::GetSuper);
+                self.emit_byte(name);
             }
-            ExprKind::Lit(lit) => {
-                let constant = self.bytecode_block.add_constant(lit.symbol.into());
-                assert!(constant < u8::MAX as usize);
-                // FIXME: Line stubbed for now
-                self.bytecode_block.write_u8(Op::ConstantSmall.into(), 111);
-                self.bytecode_block.write_u8(constant as u8, 111);
+            ExprKind::Assign(ident, value) => {
+                self.visit_expr(value);
+                let (_, op, operand) = self.resolve_variable(ident);
+                self.emit_op(op);
+                self.emit_byte(operand);
             }
         }
     }
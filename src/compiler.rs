@@ -1,30 +1,96 @@
+use std::fmt::Write;
+
 use logos::Logos;
 
 use crate::{
-    bytecode::Bytecode, bytecode_compiler::BytecodeCompiler, parser::Parser, token::Token,
+    bytecode::Bytecode,
+    bytecode_compiler::BytecodeCompiler,
+    diagnostics::Diagnostic,
+    heap::Heap,
+    parser::Parser,
+    token::{next_token, Span, Token},
 };
 
-pub struct Compiler {}
+/// Debug output that can be toggled at runtime, all of it is written to the diagnostics writer
+/// by the caller of the compiler
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DebugOptions {
+    pub print_tokens: bool,
+    pub print_ast: bool,
+    pub print_disassembly: bool,
+    pub trace_execution: bool,
+}
+
+#[derive(Default)]
+pub struct Compiler {
+    options: DebugOptions,
+}
 
 impl Compiler {
-    pub fn compile(&self, code: &str) -> Bytecode {
-        println!("Started compiling");
+    pub fn new(options: DebugOptions) -> Self {
+        Self { options }
+    }
+
+    /// Compiles `code`, allocating constants on `heap` and appending debug output to `dump`
+    /// All errors found in the code are returned in source order, to be rendered by the caller
+    pub fn compile(
+        &self,
+        code: &str,
+        heap: &mut Heap,
+        dump: &mut String,
+    ) -> Result<Bytecode, Vec<Diagnostic>> {
+        self.compile_with_roots(code, heap, &|_| {}, dump)
+    }
+
+    /// Like `compile`, for heaps with objects that are still in use, `mark_roots` marks them
+    /// whenever garbage is collected during compilation
+    /// The constants of the returned bytecode are not rooted, it has to be put on the heap
+    /// before anything else is allocated
+    pub fn compile_with_roots(
+        &self,
+        code: &str,
+        heap: &mut Heap,
+        mark_roots: &dyn Fn(&mut Heap),
+        dump: &mut String,
+    ) -> Result<Bytecode, Vec<Diagnostic>> {
+        // Writing to a string can't fail, the caller handles failures to write the dump out
+        if self.options.print_tokens {
+            Self::print_tokens(code, dump);
+        }
 
         let mut lex = Token::lexer(code);
         let mut parser = Parser::new(&mut lex);
-        let ast = parser.parse_root().unwrap();
-        println!("{:?}", ast);
-        let bytecode_compiler = BytecodeCompiler::new(&ast);
-        let bytecode = bytecode_compiler.compile();
+        let (ast, mut errors) = parser.parse_root();
+        if self.options.print_ast {
+            let _ = writeln!(dump, "{:#?}", ast);
+        }
 
-        println!("{}", bytecode.disassemble("test"));
+        // The declarations parsed without errors are compiled regardless, to report their errors
+        let bytecode_compiler = BytecodeCompiler::new(&ast, heap, mark_roots);
+        let bytecode = match bytecode_compiler.compile() {
+            Ok(bytecode) if errors.is_empty() => bytecode,
+            Ok(_) => return Err(errors),
+            Err(compile_errors) => {
+                errors.extend(compile_errors);
+                // Stable, so errors at the same position keep the order they were found in
+                errors.sort_by_key(|error| error.span.start);
+                return Err(errors);
+            }
+        };
+        if self.options.print_disassembly {
+            dump.push_str(&bytecode.disassemble("<script>"));
+        }
 
-        bytecode
+        Ok(bytecode)
     }
-}
 
-impl Default for Compiler {
-    fn default() -> Self {
-        Self {}
+    // Runs a separate lexer over the source, so the parser is not affected
+    fn print_tokens(code: &str, dump: &mut String) {
+        let mut lex = Token::lexer(code);
+        while let Some(token) = next_token(&mut lex) {
+            let line = Span::of_token(&lex).line;
+            let slice = lex.slice();
+            let _ = writeln!(dump, "{line: >4} {token:?} '{slice}'");
+        }
     }
 }
@@ -0,0 +1,357 @@
+/*
+Structured errors reported by the lexer, parser, compiler and VM.
+Diagnostics are collected as values and rendered once the source is at hand, either in the
+plain format of clox, which the test suite and other Lox tooling expect, or with the
+offending source lines and caret underlines for humans.
+*/
+
+use std::fmt::{self, Write};
+
+use crate::token::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Phase of the interpreter that reports a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Lexing,
+    Parsing,
+    Compiling,
+    Runtime,
+}
+
+/// Identifies the kind of a diagnostic independent of its message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Code {
+    // Lexing
+    UnexpectedCharacter,
+    UnterminatedString,
+    InvalidNumber,
+    // Parsing
+    ExpectedToken,
+    InvalidAssignmentTarget,
+    TooManyArguments,
+    ExpectedExpression,
+    NestingTooDeep,
+    // Compiling
+    ReturnAtTopLevel,
+    ReturnFromInitializer,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    InheritFromSelf,
+    LocalInOwnInitializer,
+    DuplicateLocal,
+    TooManyLocals,
+    TooManyUpvalues,
+    TooMuchCode,
+    TooManyConstants,
+    // Runtime
+    TypeError,
+    UndefinedVariable,
+    UndefinedProperty,
+    NotCallable,
+    ArityMismatch,
+    StackOverflow,
+}
+
+impl Code {
+    pub fn phase(self) -> Phase {
+        match self {
+            Code::UnexpectedCharacter | Code::UnterminatedString | Code::InvalidNumber => {
+                Phase::Lexing
+            }
+            Code::ExpectedToken
+            | Code::InvalidAssignmentTarget
+            | Code::TooManyArguments
+            | Code::ExpectedExpression
+            | Code::NestingTooDeep => Phase::Parsing,
+            Code::ReturnAtTopLevel
+            | Code::ReturnFromInitializer
+            | Code::ThisOutsideClass
+            | Code::SuperOutsideClass
+            | Code::SuperWithoutSuperclass
+            | Code::InheritFromSelf
+            | Code::LocalInOwnInitializer
+            | Code::DuplicateLocal
+            | Code::TooManyLocals
+            | Code::TooManyUpvalues
+            | Code::TooMuchCode
+            | Code::TooManyConstants => Phase::Compiling,
+            Code::TypeError
+            | Code::UndefinedVariable
+            | Code::UndefinedProperty
+            | Code::NotCallable
+            | Code::ArityMismatch
+            | Code::StackOverflow => Phase::Runtime,
+        }
+    }
+
+    // Numbered by phase, so the first digits tell where an error comes from
+    fn number(self) -> u16 {
+        match self {
+            Code::UnexpectedCharacter => 101,
+            Code::UnterminatedString => 102,
+            Code::InvalidNumber => 103,
+            Code::ExpectedToken => 201,
+            Code::InvalidAssignmentTarget => 202,
+            Code::TooManyArguments => 203,
+            Code::ExpectedExpression => 204,
+            Code::NestingTooDeep => 205,
+            Code::ReturnAtTopLevel => 301,
+            Code::ReturnFromInitializer => 302,
+            Code::ThisOutsideClass => 303,
+            Code::SuperOutsideClass => 304,
+            Code::SuperWithoutSuperclass => 305,
+            Code::InheritFromSelf => 306,
+            Code::LocalInOwnInitializer => 307,
+            Code::DuplicateLocal => 308,
+            Code::TooManyLocals => 309,
+            Code::TooManyUpvalues => 310,
+            Code::TooMuchCode => 311,
+            Code::TooManyConstants => 312,
+            Code::TypeError => 401,
+            Code::UndefinedVariable => 402,
+            Code::UndefinedProperty => 403,
+            Code::NotCallable => 404,
+            Code::ArityMismatch => 405,
+            Code::StackOverflow => 406,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:04}", self.number())
+    }
+}
+
+/// Secondary location related to a diagnostic
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    /// Where the problem is, the lexeme it covers is named by the plain format
+    pub span: Span,
+    pub labels: Vec<Label>,
+    /// Additional explanations, runtime errors keep their stack trace here
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// The format of clox, e.g. `[line 1] Error at 'x': message`
+    #[default]
+    Plain,
+    /// Shows the source lines with the problem underlined
+    Rich { color: bool },
+}
+
+// ANSI escape sequences
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Renderer {
+    style: Style,
+}
+
+impl Renderer {
+    pub const fn new(style: Style) -> Renderer {
+        Renderer { style }
+    }
+
+    pub fn style(&self) -> Style {
+        self.style
+    }
+
+    /// Renders the diagnostic with the source it refers to, every line ends with a newline
+    pub fn render(&self, diagnostic: &Diagnostic, source: &str) -> String {
+        match self.style {
+            Style::Plain => Self::render_plain(diagnostic, source),
+            Style::Rich { color } => Self::render_rich(diagnostic, source, color),
+        }
+    }
+
+    fn render_plain(diagnostic: &Diagnostic, source: &str) -> String {
+        let mut rendered = String::new();
+        let severity = match diagnostic.severity {
+            Severity::Error => "Error",
+        };
+        let Diagnostic { span, message, .. } = diagnostic;
+
+        match diagnostic.code.phase() {
+            Phase::Runtime => {
+                writeln!(rendered, "{message}").unwrap();
+                for note in &diagnostic.notes {
+                    writeln!(rendered, "{note}").unwrap();
+                }
+            }
+            // clox reports where scanning stopped, i.e. after the newlines of a string
+            Phase::Lexing => {
+                let newlines = source
+                    .get(span.start..span.end)
+                    .map_or(0, |text| text.matches('\n').count());
+                let line = span.line + newlines;
+                writeln!(rendered, "[line {line}] {severity}: {message}").unwrap();
+            }
+            // Limits of the bytecode format have no lexeme to point at
+            _ if diagnostic.code == Code::TooMuchCode => {
+                writeln!(rendered, "[line {}] {severity}: {message}", span.line).unwrap();
+            }
+            Phase::Parsing | Phase::Compiling => {
+                // Only the end of the input has an empty span, lexemes are quoted like in clox
+                let lexeme = match source.get(span.start..span.end) {
+                    Some(lexeme) if !lexeme.is_empty() => format!("'{lexeme}'"),
+                    _ => "end".to_owned(),
+                };
+                writeln!(
+                    rendered,
+                    "[line {}] {severity} at {lexeme}: {message}",
+                    span.line
+                )
+                .unwrap();
+            }
+        }
+        rendered
+    }
+
+    fn render_rich(diagnostic: &Diagnostic, source: &str, color: bool) -> String {
+        let paint = |style: &'static str| if color { style } else { "" };
+        let reset = paint(RESET);
+        let gutter_color = paint(BLUE);
+        let severity_color = match diagnostic.severity {
+            Severity::Error => paint(RED),
+        };
+
+        let mut rendered = String::new();
+        writeln!(
+            rendered,
+            "{severity_color}{}[{}]{reset}{}: {}{reset}",
+            diagnostic.severity,
+            diagnostic.code,
+            paint(BOLD),
+            diagnostic.message
+        )
+        .unwrap();
+
+        // The primary span is underlined with carets, labels with dashes
+        let mut annotations = vec![(diagnostic.span, '^', severity_color, "")];
+        for label in &diagnostic.labels {
+            annotations.push((label.span, '-', gutter_color, &label.message));
+        }
+        annotations.sort_by_key(|(span, ..)| (span.line, span.column));
+
+        let width = annotations
+            .iter()
+            .map(|(span, ..)| span.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = format!("{gutter_color}{:width$} |{reset}", "");
+
+        let span = diagnostic.span;
+        writeln!(
+            rendered,
+            "{gutter_color}{:width$}-->{reset} {}:{}",
+            "", span.line, span.column
+        )
+        .unwrap();
+        writeln!(rendered, "{gutter}").unwrap();
+
+        let mut previous_line = None;
+        for (span, marker, marker_color, message) in annotations {
+            let line = source
+                .lines()
+                .nth(span.line.saturating_sub(1))
+                .unwrap_or("");
+            if previous_line != Some(span.line) {
+                writeln!(
+                    rendered,
+                    "{gutter_color}{:>width$} |{reset} {line}",
+                    span.line
+                )
+                .unwrap();
+                previous_line = Some(span.line);
+            }
+
+            // Tabs are kept, so the markers line up with the source
+            let indent: String = line
+                .chars()
+                .take(span.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let underlined = source
+                .get(span.start..span.end)
+                .and_then(|text| text.lines().next())
+                .map_or(0, |text| text.chars().count())
+                .max(1);
+            let markers = marker.to_string().repeat(underlined);
+            let message = if message.is_empty() {
+                String::new()
+            } else {
+                format!(" {message}")
+            };
+            writeln!(
+                rendered,
+                "{gutter} {indent}{marker_color}{markers}{message}{reset}"
+            )
+            .unwrap();
+        }
+
+        for note in &diagnostic.notes {
+            writeln!(
+                rendered,
+                "{gutter_color}{:width$} ={reset} note: {note}",
+                ""
+            )
+            .unwrap();
+        }
+        rendered
+    }
+}
@@ -0,0 +1,209 @@
+/*
+Precise mark-and-sweep garbage collector.
+The heap can't see the roots itself, the owner of the roots marks them with `mark_value`,
+`mark_object` and `mark_table` before calling `collect`. Marked objects are put on a gray
+worklist, tracing blackens them by marking everything they reference until the list is empty.
+The intern table only holds weak references, unmarked strings are removed from it before
+the sweep frees every unmarked object.
+*/
+
+use std::{cell::Cell, mem, ptr::NonNull};
+
+use crate::{
+    lox_value::LoxValue,
+    object::{Obj, ObjKind, ObjRef, ObjString},
+    table::{hash_str, Table},
+};
+
+// After a collection the next one is due once the surviving memory has grown by this factor
+const GC_HEAP_GROW_FACTOR: usize = 2;
+// Small programs never collect
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+/// Owns every object created by the compiler and the VM, unreachable objects are freed by
+/// `collect`
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<NonNull<Obj>>,
+    // Every string is interned, so there is at most one object per distinct content
+    // Only the keys are used, the values are always nil
+    strings: Table,
+    // Marked objects whose references have not been traced yet
+    gray: Vec<ObjRef>,
+    // Estimated size of the live objects, including the memory they own
+    bytes_allocated: usize,
+    next_gc: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            objects: vec![],
+            strings: Table::new(),
+            gray: vec![],
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+        }
+    }
+
+    /// Allocates an object, never collects garbage
+    /// Callers check `should_collect` beforehand, while every object they still need is rooted
+    pub fn alloc(&mut self, kind: ObjKind) -> ObjRef {
+        let obj = Obj {
+            kind,
+            is_marked: Cell::new(false),
+        };
+        self.bytes_allocated += allocated_size(&obj);
+        let ptr = NonNull::from(Box::leak(Box::new(obj)));
+        self.objects.push(ptr);
+        unsafe { ObjRef::from_raw(ptr) }
+    }
+
+    /// Returns the string object with the given contents, allocating it only if it is new
+    pub fn intern(&mut self, value: &str) -> ObjRef {
+        let hash = hash_str(value);
+        if let Some(interned) = self.strings.find_string(value, hash) {
+            return interned;
+        }
+
+        let string = self.alloc(ObjKind::Str(ObjString {
+            value: value.into(),
+            hash,
+        }));
+        self.strings.insert(string, LoxValue::Nil);
+        string
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    /// Whether enough memory was allocated since the last collection to collect again
+    pub fn should_collect(&self) -> bool {
+        cfg!(feature = "gc-stress") || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn mark_value(&mut self, value: LoxValue) {
+        if let LoxValue::Obj(obj) = value {
+            self.mark_object(obj);
+        }
+    }
+
+    pub fn mark_object(&mut self, obj: ObjRef) {
+        // Marked objects are already traced or on the worklist, this also ends cycles
+        if obj.get().is_marked.replace(true) {
+            return;
+        }
+        self.gray.push(obj);
+    }
+
+    pub fn mark_table(&mut self, table: &Table) {
+        for (key, value) in table.iter() {
+            self.mark_object(key);
+            self.mark_value(value);
+        }
+    }
+
+    /// Frees every object that is not reachable from the marked roots
+    pub fn collect(&mut self) {
+        self.trace_references();
+        // Interned strings are only kept alive by references from elsewhere
+        self.strings.retain(|string, _| string.get().is_marked.get());
+        self.sweep();
+        self.next_gc = (self.bytes_allocated * GC_HEAP_GROW_FACTOR).max(GC_INITIAL_THRESHOLD);
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(obj) = self.gray.pop() {
+            self.blacken(obj);
+        }
+    }
+
+    // Marks every object referenced by a marked object
+    fn blacken(&mut self, obj: ObjRef) {
+        match &obj.get().kind {
+            ObjKind::Str(_) => {}
+            ObjKind::Function(function) => {
+                if let Some(name) = function.name {
+                    self.mark_object(name);
+                }
+                for constant in function.bytecode.constants() {
+                    self.mark_value(*constant);
+                }
+            }
+            ObjKind::Closure(closure) => {
+                self.mark_object(closure.function);
+                for upvalue in &closure.upvalues {
+                    self.mark_object(*upvalue);
+                }
+            }
+            // Open upvalues point into the stack, which is a root
+            ObjKind::Upvalue(upvalue) => {
+                if let Some(value) = upvalue.closed() {
+                    self.mark_value(value);
+                }
+            }
+            ObjKind::Class(class) => {
+                self.mark_object(class.name);
+                self.mark_table(&class.methods.borrow());
+            }
+            ObjKind::Instance(instance) => {
+                self.mark_object(instance.class);
+                self.mark_table(&instance.fields.borrow());
+            }
+            ObjKind::BoundMethod(bound) => {
+                self.mark_value(bound.receiver);
+                self.mark_object(bound.method);
+            }
+        }
+    }
+
+    fn sweep(&mut self) {
+        let mut bytes_allocated = 0;
+        self.objects.retain(|ptr| {
+            let obj = unsafe { ptr.as_ref() };
+            // Survivors are unmarked again for the next collection
+            if obj.is_marked.replace(false) {
+                bytes_allocated += allocated_size(obj);
+                return true;
+            }
+            // Nothing reachable refers to the object anymore, so it is freed exactly once
+            drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+            false
+        });
+        // Objects like instances grow after their allocation, so the estimate is refreshed
+        self.bytes_allocated = bytes_allocated;
+    }
+}
+
+// Size of the object including the memory it owns
+fn allocated_size(obj: &Obj) -> usize {
+    let owned = match &obj.kind {
+        ObjKind::Str(string) => string.value.len(),
+        ObjKind::Function(function) => function.bytecode.allocated_bytes(),
+        ObjKind::Closure(closure) => closure.upvalues.capacity() * mem::size_of::<ObjRef>(),
+        ObjKind::Upvalue(_) | ObjKind::BoundMethod(_) => 0,
+        ObjKind::Class(class) => class.methods.borrow().allocated_bytes(),
+        ObjKind::Instance(instance) => instance.fields.borrow().allocated_bytes(),
+    };
+    mem::size_of::<Obj>() + owned
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for ptr in self.objects.drain(..) {
+            // Every pointer was created by Box::leak in alloc and is freed exactly once
+            drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+        }
+    }
+}
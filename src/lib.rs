@@ -2,10 +2,14 @@ pub mod ast;
 pub mod bytecode;
 pub mod bytecode_compiler;
 pub mod compiler;
+pub mod diagnostics;
+pub mod heap;
 pub mod lox_value;
+pub mod object;
 pub mod opcodes;
 pub mod parser;
 pub mod repl;
 pub mod stack;
+pub mod table;
 pub mod token;
 pub mod vm;
@@ -1,8 +1,51 @@
 use std::fmt::Display;
 
-#[derive(Debug, Clone, Copy)]
+use crate::object::{ObjClass, ObjFunction, ObjInstance, ObjRef, ObjString};
+
+// Values of different types are never equal, numbers compare as IEEE 754 floats
+// Objects compare by identity, as strings are interned this also covers string equality
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub enum LoxValue {
+    #[default]
+    Nil,
+    Bool(bool),
     Number(f64),
+    Obj(ObjRef),
+}
+
+impl LoxValue {
+    // Only nil and false are falsey, every other value is truthy
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, LoxValue::Nil | LoxValue::Bool(false))
+    }
+
+    pub fn as_string(&self) -> Option<&ObjString> {
+        match self {
+            LoxValue::Obj(obj) => obj.as_string(),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&ObjFunction> {
+        match self {
+            LoxValue::Obj(obj) => obj.as_function(),
+            _ => None,
+        }
+    }
+
+    pub fn as_class(&self) -> Option<&ObjClass> {
+        match self {
+            LoxValue::Obj(obj) => obj.as_class(),
+            _ => None,
+        }
+    }
+
+    pub fn as_instance(&self) -> Option<&ObjInstance> {
+        match self {
+            LoxValue::Obj(obj) => obj.as_instance(),
+            _ => None,
+        }
+    }
 }
 
 impl From<f64> for LoxValue {
@@ -17,17 +60,57 @@ impl From<i32> for LoxValue {
     }
 }
 
-impl Default for LoxValue {
-    fn default() -> Self {
-        // FIXME: replace with nil later
-        LoxValue::Number(0.0)
+impl From<bool> for LoxValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
     }
 }
 
 impl Display for LoxValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LoxValue::Number(num) => write!(f, "{num}"),
+            LoxValue::Nil => write!(f, "nil"),
+            LoxValue::Bool(b) => write!(f, "{b}"),
+            LoxValue::Number(num) => write!(f, "{}", format_number(*num)),
+            LoxValue::Obj(obj) => write!(f, "{obj}"),
         }
     }
 }
+
+// Mirrors printf's %g as used by clox: six significant digits without trailing zeros,
+// switching to scientific notation for very large and very small magnitudes
+fn format_number(num: f64) -> String {
+    const PRECISION: i32 = 6;
+
+    if num.is_nan() {
+        return "nan".to_owned();
+    }
+    if num.is_infinite() {
+        return if num < 0.0 { "-inf" } else { "inf" }.to_owned();
+    }
+    if num == 0.0 {
+        return if num.is_sign_negative() { "-0" } else { "0" }.to_owned();
+    }
+
+    // Round to the significant digits first, as rounding may change the exponent
+    let scientific = format!("{:.*e}", (PRECISION - 1) as usize, num);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+
+    if !(-4..PRECISION).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        let mantissa = trim_fraction(mantissa);
+        format!("{mantissa}e{sign}{:02}", exponent.abs())
+    } else {
+        let decimals = (PRECISION - 1 - exponent) as usize;
+        trim_fraction(&format!("{num:.decimals$}")).to_owned()
+    }
+}
+
+fn trim_fraction(digits: &str) -> &str {
+    if digits.contains('.') {
+        digits.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        digits
+    }
+}
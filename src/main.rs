@@ -1,5 +1,165 @@
-use loxidize::repl;
+use std::{
+    io::{self, IsTerminal, Write},
+    path::Path,
+    process::ExitCode,
+};
 
-fn main() {
-    repl::repl();
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use loxidize::{
+    bytecode::Bytecode,
+    compiler::{Compiler, DebugOptions},
+    diagnostics::{Diagnostic, Renderer, Style},
+    heap::Heap,
+    repl,
+    vm::{Error, VM},
+};
+
+fn cli() -> Command {
+    let file_arg = || Arg::new("file").help("Path to a Lox script").required(true);
+
+    let debug_flag = |name: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .help(help)
+            .action(ArgAction::SetTrue)
+            .global(true)
+    };
+
+    Command::new("loxidize")
+        .about("A bytecode interpreter for Lox")
+        .arg(debug_flag("print-tokens", "Print the token stream"))
+        .arg(debug_flag("print-ast", "Print the syntax tree"))
+        .arg(debug_flag(
+            "print-disassembly",
+            "Print the compiled bytecode",
+        ))
+        .arg(debug_flag(
+            "trace-execution",
+            "Trace every executed instruction",
+        ))
+        .arg(
+            Arg::new("error-format")
+                .long("error-format")
+                .help("How errors are reported, plain matches the output of clox")
+                .value_parser(["plain", "rich"])
+                .default_value("plain")
+                .global(true),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Run a Lox script")
+                .arg(file_arg()),
+        )
+        .subcommand(Command::new("repl").about("Start an interactive session"))
+        .subcommand(
+            Command::new("disasm")
+                .about("Compile a Lox script and print its bytecode")
+                .arg(file_arg()),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Compile a Lox script without running it")
+                .arg(file_arg()),
+        )
+}
+
+fn debug_options(matches: &ArgMatches) -> DebugOptions {
+    DebugOptions {
+        print_tokens: matches.get_flag("print-tokens"),
+        print_ast: matches.get_flag("print-ast"),
+        print_disassembly: matches.get_flag("print-disassembly"),
+        trace_execution: matches.get_flag("trace-execution"),
+    }
+}
+
+fn renderer(matches: &ArgMatches) -> Renderer {
+    let style = match matches
+        .get_one::<String>("error-format")
+        .map(String::as_str)
+    {
+        Some("rich") => Style::Rich {
+            color: io::stderr().is_terminal(),
+        },
+        _ => Style::Plain,
+    };
+    Renderer::new(style)
+}
+
+// Compile errors of the commands that don't run the code
+fn report(matches: &ArgMatches, errors: &[Diagnostic], source: &str) -> ExitCode {
+    let renderer = renderer(matches);
+    for error in errors {
+        eprint!("{}", renderer.render(error, source));
+    }
+    ExitCode::from(Error::Compile.exit_code())
+}
+
+// Compiles for the commands that don't run the code
+fn compile(matches: &ArgMatches, source: &str, heap: &mut Heap) -> Result<Bytecode, ExitCode> {
+    let mut dump = String::new();
+    let compiled = Compiler::new(debug_options(matches)).compile(source, heap, &mut dump);
+    eprint!("{dump}");
+    compiled.map_err(|errors| report(matches, &errors, source))
+}
+
+fn read_source(matches: &ArgMatches) -> Result<(String, String), ExitCode> {
+    let path = matches
+        .get_one::<String>("file")
+        .expect("file is a required argument");
+
+    match std::fs::read_to_string(Path::new(path)) {
+        Ok(source) => Ok((path.clone(), source)),
+        Err(e) => {
+            eprintln!("Could not read file \"{path}\": {e}");
+            Err(ExitCode::from(Error::Io.exit_code()))
+        }
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), ExitCode> {
+    let (_, source) = read_source(matches)?;
+    let mut vm = VM::default();
+    vm.set_debug_options(debug_options(matches));
+    vm.set_renderer(renderer(matches));
+    vm.interpret(&source)
+        .map_err(|e| ExitCode::from(e.exit_code()))
+}
+
+fn disasm(matches: &ArgMatches) -> Result<(), ExitCode> {
+    let (path, source) = read_source(matches)?;
+    // Constants point into the heap, so it has to outlive the disassembly
+    let mut heap = Heap::new();
+    let bytecode = compile(matches, &source, &mut heap)?;
+    io::stdout()
+        .write_all(bytecode.disassemble(&path).as_bytes())
+        .map_err(|_| ExitCode::from(Error::Io.exit_code()))
+}
+
+fn check(matches: &ArgMatches) -> Result<(), ExitCode> {
+    let (_, source) = read_source(matches)?;
+    compile(matches, &source, &mut Heap::new()).map(|_| ())
+}
+
+fn main() -> ExitCode {
+    let matches = cli().get_matches();
+
+    let result = match matches.subcommand() {
+        Some(("run", sub)) => run(sub),
+        Some(("disasm", sub)) => disasm(sub),
+        Some(("check", sub)) => check(sub),
+        Some(("repl", sub)) => {
+            repl::repl(debug_options(sub), renderer(sub));
+            Ok(())
+        }
+        None => {
+            repl::repl(debug_options(&matches), renderer(&matches));
+            Ok(())
+        }
+        Some((name, _)) => unreachable!("Unknown subcommand {name}"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => code,
+    }
 }
@@ -0,0 +1,262 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    ptr::NonNull,
+};
+
+use crate::{bytecode::Bytecode, lox_value::LoxValue, stack::Sp, table::Table, vm::STACK_SIZE};
+
+#[derive(Debug)]
+pub struct Obj {
+    pub kind: ObjKind,
+    // Set while the garbage collector traces reachable objects, cleared again by the sweep
+    pub is_marked: Cell<bool>,
+}
+
+#[derive(Debug)]
+pub enum ObjKind {
+    Str(ObjString),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+}
+
+#[derive(Debug)]
+pub struct ObjString {
+    pub value: Box<str>,
+    // Cached for hash table lookups
+    pub hash: u32,
+}
+
+#[derive(Debug)]
+pub struct ObjFunction {
+    pub arity: u8,
+    pub upvalue_count: usize,
+    pub bytecode: Bytecode,
+    // None for the top-level script
+    pub name: Option<ObjRef>,
+}
+
+impl fmt::Display for ObjFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "<fn {name}>"),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+/// A function together with the variables it captured, every function is called through one
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+impl ObjClosure {
+    pub fn function(&self) -> &ObjFunction {
+        self.function
+            .as_function()
+            .expect("Closures always wrap a function")
+    }
+}
+
+/// A captured variable, shared by every closure capturing the same variable
+#[derive(Debug)]
+pub struct ObjUpvalue {
+    location: RefCell<UpvalueLocation>,
+}
+
+#[derive(Debug)]
+enum UpvalueLocation {
+    // The variable still lives in its slot on the stack
+    Open(Sp<STACK_SIZE>),
+    // The variable outlived its frame and was moved into the upvalue
+    Closed(LoxValue),
+}
+
+impl ObjUpvalue {
+    pub fn new(slot: Sp<STACK_SIZE>) -> ObjUpvalue {
+        ObjUpvalue {
+            location: RefCell::new(UpvalueLocation::Open(slot)),
+        }
+    }
+
+    pub fn get(&self) -> LoxValue {
+        match &*self.location.borrow() {
+            UpvalueLocation::Open(slot) => slot.get_value(),
+            UpvalueLocation::Closed(value) => *value,
+        }
+    }
+
+    pub fn set(&self, value: LoxValue) {
+        match &mut *self.location.borrow_mut() {
+            UpvalueLocation::Open(slot) => slot.write_value(&value),
+            UpvalueLocation::Closed(closed) => *closed = value,
+        }
+    }
+
+    /// The stack slot of the variable, None once the upvalue is closed
+    pub fn slot(&self) -> Option<Sp<STACK_SIZE>> {
+        match &*self.location.borrow() {
+            UpvalueLocation::Open(slot) => Some(slot.clone()),
+            UpvalueLocation::Closed(_) => None,
+        }
+    }
+
+    /// The value of a closed upvalue, open ones refer to a slot on the stack instead
+    pub fn closed(&self) -> Option<LoxValue> {
+        match &*self.location.borrow() {
+            UpvalueLocation::Open(_) => None,
+            UpvalueLocation::Closed(value) => Some(*value),
+        }
+    }
+
+    /// Moves the variable off the stack, must happen before its slot is reused
+    pub fn close(&self) {
+        let value = self.get();
+        *self.location.borrow_mut() = UpvalueLocation::Closed(value);
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: ObjRef,
+    // Closures keyed by method name, filled in while the class declaration executes
+    pub methods: RefCell<Table>,
+}
+
+impl ObjClass {
+    pub fn new(name: ObjRef) -> ObjClass {
+        ObjClass {
+            name,
+            methods: RefCell::new(Table::new()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjInstance {
+    pub class: ObjRef,
+    pub fields: RefCell<Table>,
+}
+
+impl ObjInstance {
+    pub fn new(class: ObjRef) -> ObjInstance {
+        ObjInstance {
+            class,
+            fields: RefCell::new(Table::new()),
+        }
+    }
+
+    pub fn class(&self) -> &ObjClass {
+        self.class.as_class().expect("Instances are created from classes")
+    }
+}
+
+/// A method accessed on an instance, remembers the instance to bind `this` when called
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: LoxValue,
+    pub method: ObjRef,
+}
+
+impl fmt::Display for Obj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ObjKind::Str(string) => write!(f, "{}", string.value),
+            ObjKind::Function(function) => write!(f, "{function}"),
+            ObjKind::Closure(closure) => write!(f, "{}", closure.function()),
+            ObjKind::Upvalue(_) => write!(f, "upvalue"),
+            ObjKind::Class(class) => write!(f, "{}", class.name),
+            ObjKind::Instance(instance) => write!(f, "{} instance", instance.class().name),
+            ObjKind::BoundMethod(bound) => write!(f, "{}", bound.method),
+        }
+    }
+}
+
+/// Copyable handle to an object owned by a `Heap`
+/// Handles compare and hash by identity, which for interned strings is equality by content
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct ObjRef {
+    ptr: NonNull<Obj>,
+}
+
+impl ObjRef {
+    /// # Safety
+    /// `ptr` must point to an object owned by a heap, the handle must not be used after the
+    /// heap has freed the object.
+    pub unsafe fn from_raw(ptr: NonNull<Obj>) -> ObjRef {
+        ObjRef { ptr }
+    }
+
+    pub fn as_ptr(&self) -> NonNull<Obj> {
+        self.ptr
+    }
+
+    #[inline]
+    pub fn get(&self) -> &Obj {
+        // Objects are only freed once the garbage collector proved them unreachable, so every
+        // handle still in use points to a live object
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn as_string(&self) -> Option<&ObjString> {
+        match &self.get().kind {
+            ObjKind::Str(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&ObjFunction> {
+        match &self.get().kind {
+            ObjKind::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    pub fn as_closure(&self) -> Option<&ObjClosure> {
+        match &self.get().kind {
+            ObjKind::Closure(closure) => Some(closure),
+            _ => None,
+        }
+    }
+
+    pub fn as_upvalue(&self) -> Option<&ObjUpvalue> {
+        match &self.get().kind {
+            ObjKind::Upvalue(upvalue) => Some(upvalue),
+            _ => None,
+        }
+    }
+
+    pub fn as_class(&self) -> Option<&ObjClass> {
+        match &self.get().kind {
+            ObjKind::Class(class) => Some(class),
+            _ => None,
+        }
+    }
+
+    pub fn as_instance(&self) -> Option<&ObjInstance> {
+        match &self.get().kind {
+            ObjKind::Instance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+}
+
+// Only shows the printed form, as instances may refer to themselves through their fields
+impl fmt::Debug for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {}", self.ptr, self.get())
+    }
+}
+
+impl fmt::Display for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
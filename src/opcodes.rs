@@ -2,28 +2,80 @@ use std::fmt::{self};
 
 use num_enum::TryFromPrimitive;
 
-#[derive(TryFromPrimitive)]
+#[derive(TryFromPrimitive, Clone, Copy)]
 #[repr(u8)]
 pub enum Op {
     // Constant operations
     ConstantSmall,
+    Nil,
+    True,
+    False,
+    // Stack operations
+    Pop,
+    // Variable operations
+    GetLocal,
+    SetLocal,
+    DefineGlobal,
+    GetGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    CloseUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
     // Arithmetic operations
     Add,
     Subtract,
     Multiply,
     Divide,
     Negate,
+    // Comparison and logical operations
+    Equal,
+    Greater,
+    Less,
+    Not,
+    // Statement operations
+    Print,
     // Control flow operations
-    Ret,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    // Function operations
+    Call,
+    Closure,
+    Return,
+    // Class operations
+    Class,
+    Inherit,
+    Method,
+    // Calls a method by name without creating a bound method
+    Invoke,
+    SuperInvoke,
 }
 
 impl Op {
     // Provides the count of u8 operands of a given instruction
     pub fn operand_count(self) -> usize {
         match self {
-            Op::Ret => 0,
+            Op::Return | Op::Pop | Op::Print | Op::CloseUpvalue | Op::Inherit => 0,
+            Op::Nil | Op::True | Op::False => 0,
             Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Negate => 0,
+            Op::Equal | Op::Greater | Op::Less | Op::Not => 0,
             Op::ConstantSmall => 1,
+            Op::GetLocal | Op::SetLocal => 1,
+            Op::GetUpvalue | Op::SetUpvalue => 1,
+            // Followed by two more bytes per captured variable, see the upvalue count of the
+            // function constant
+            Op::Closure => 1,
+            // The argument count
+            Op::Call => 1,
+            Op::DefineGlobal | Op::GetGlobal | Op::SetGlobal => 1,
+            Op::GetProperty | Op::SetProperty | Op::GetSuper | Op::Class | Op::Method => 1,
+            // The name of the method and the argument count
+            Op::Invoke | Op::SuperInvoke => 2,
+            // Jump offsets are stored as big-endian u16
+            Op::Jump | Op::JumpIfFalse | Op::Loop => 2,
         }
     }
 }
@@ -35,15 +87,47 @@ impl From<Op> for u8 {
 }
 
 impl fmt::Display for Op {
+    // Padded, so the disassembler can line up the operands
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            Op::Ret => write!(f, "OP_RETURN"),
-            Op::Add => write!(f, "OP_ADD"),
-            Op::Subtract => write!(f, "OP_SUBTRACT"),
-            Op::Multiply => write!(f, "OP_MULTIPLY"),
-            Op::Divide => write!(f, "OP_DIVIDE"),
-            Op::Negate => write!(f, "OP_NEGATE"),
-            Op::ConstantSmall => write!(f, "OP_CONSTANT_SMALL"),
-        }
+        let name = match *self {
+            Op::Return => "OP_RETURN",
+            Op::Add => "OP_ADD",
+            Op::Subtract => "OP_SUBTRACT",
+            Op::Multiply => "OP_MULTIPLY",
+            Op::Divide => "OP_DIVIDE",
+            Op::Negate => "OP_NEGATE",
+            Op::Equal => "OP_EQUAL",
+            Op::Greater => "OP_GREATER",
+            Op::Less => "OP_LESS",
+            Op::Not => "OP_NOT",
+            Op::ConstantSmall => "OP_CONSTANT_SMALL",
+            Op::Nil => "OP_NIL",
+            Op::True => "OP_TRUE",
+            Op::False => "OP_FALSE",
+            Op::Pop => "OP_POP",
+            Op::GetLocal => "OP_GET_LOCAL",
+            Op::SetLocal => "OP_SET_LOCAL",
+            Op::DefineGlobal => "OP_DEFINE_GLOBAL",
+            Op::GetGlobal => "OP_GET_GLOBAL",
+            Op::SetGlobal => "OP_SET_GLOBAL",
+            Op::Print => "OP_PRINT",
+            Op::Jump => "OP_JUMP",
+            Op::JumpIfFalse => "OP_JUMP_IF_FALSE",
+            Op::Loop => "OP_LOOP",
+            Op::Call => "OP_CALL",
+            Op::Closure => "OP_CLOSURE",
+            Op::GetUpvalue => "OP_GET_UPVALUE",
+            Op::SetUpvalue => "OP_SET_UPVALUE",
+            Op::CloseUpvalue => "OP_CLOSE_UPVALUE",
+            Op::GetProperty => "OP_GET_PROPERTY",
+            Op::SetProperty => "OP_SET_PROPERTY",
+            Op::Class => "OP_CLASS",
+            Op::Method => "OP_METHOD",
+            Op::Invoke => "OP_INVOKE",
+            Op::GetSuper => "OP_GET_SUPER",
+            Op::Inherit => "OP_INHERIT",
+            Op::SuperInvoke => "OP_SUPER_INVOKE",
+        };
+        f.pad(name)
     }
 }
@@ -1,37 +1,66 @@
+use std::mem;
+
 use crate::{
-    ast::{Ast, BinOpKind, Expr, ExprKind, Lit, Precedence},
-    token::Token,
+    ast::{
+        Ast, BinOpKind, ClassDecl, Expr, ExprKind, FunDecl, Ident, Lit, LitKind, Precedence, Stmt,
+        StmtKind, UnOp,
+    },
+    diagnostics::{Code, Diagnostic},
+    token::{next_token, LexingError, Span, Token, TokenSpan},
 };
 
+// Arguments and parameters are counted by a single byte operand
+const MAX_ARGS: usize = u8::MAX as usize;
+// Nested statements and expressions are parsed recursively, deeper nesting would overflow the
+// native stack of the parser, or later of the bytecode compiler
+const MAX_NESTING: usize = 256;
+
 pub struct Parser<'a> {
     token: Token,
     prev_token: Token,
-    prev_line: usize,
-    prev_slice: &'a str,
+    prev_span: Span,
+    // The token consumed last, where the node being parsed ends
+    last_token: Token,
+    last_span: Span,
     lexer: &'a mut logos::Lexer<'a, Token>,
-    had_error: bool,
-    at_end: bool,
+    diagnostics: Vec<Diagnostic>,
+    // Set by the first error in a declaration, so the errors following from it are not reported
+    panic_mode: bool,
+    // Statements, functions and expressions currently being parsed inside each other
+    nesting: usize,
+    // Set once the nesting limit is reached, the input skipped then leaves every enclosing node
+    // unfinished, so errors are suppressed until the outermost one is left
+    too_deep: bool,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: &'a mut logos::Lexer<'a, Token>) -> Self {
-        let parser = Parser {
+        Parser {
             // Placeholder
             token: Token::Bang,
             prev_token: Token::Bang,
-            prev_line: 0,
-            prev_slice: &"",
-            lexer: lexer,
-            had_error: false,
-            at_end: false,
-        };
-        parser
+            prev_span: Span::default(),
+            last_token: Token::Bang,
+            last_span: Span::default(),
+            lexer,
+            diagnostics: vec![],
+            panic_mode: false,
+            nesting: 0,
+            too_deep: false,
+        }
     }
 
-    pub fn expect(&mut self, token: Token) {}
+    pub fn expect(&mut self, token: Token, message: &str) {
+        if self.check(token) {
+            self.advance();
+        } else {
+            self.error(Code::ExpectedToken, message);
+        }
+    }
 
+    // Only compares the kind of token, payloads like numbers are ignored
     pub fn check(&mut self, token: Token) -> bool {
-        false
+        mem::discriminant(&self.prev_token) == mem::discriminant(&token)
     }
 
     pub fn eat(&mut self, token: Token) -> bool {
@@ -43,108 +72,570 @@ impl<'a> Parser<'a> {
     }
 
     pub fn advance(&mut self) {
-        self.prev_line = self.lexer.extras.0;
-        self.prev_slice = self.lexer.slice();
+        self.last_token = self.prev_token;
+        self.last_span = self.prev_span;
+        self.prev_span = Span::of_token(self.lexer);
         self.prev_token = self.token;
-        if let Some(token) = self.lexer.next() {
-            match token.clone() {
-                Ok(token) => {
-                    self.token = token;
-                }
-                Err(e) => self.error(&format!("Lexing error: {:?}", e)),
+        // Invalid input is reported and skipped, so the parser only ever sees valid tokens
+        self.token = loop {
+            match next_token(self.lexer) {
+                Some(Ok(token)) => break token,
+                Some(Err(error)) => self.lexing_error(&error),
+                None => break Token::EOF,
+            }
+        };
+    }
+
+    // The lexer is still positioned at the invalid input
+    fn lexing_error(&mut self, error: &LexingError) {
+        let code = match error {
+            LexingError::UnterminatedString(_) => Code::UnterminatedString,
+            LexingError::InvalidNumber(_) => Code::InvalidNumber,
+            LexingError::UnexpectedCharacter(..) | LexingError::Unspecified => {
+                Code::UnexpectedCharacter
+            }
+        };
+        self.error_at(Span::of_token(self.lexer), code, &error.to_string());
+    }
+
+    pub fn error(&mut self, code: Code, message: &str) {
+        self.error_at(self.prev_span, code, message);
+    }
+
+    pub fn error_at(&mut self, span: Span, code: Code, message: &str) {
+        if self.panic_mode || self.too_deep {
+            return;
+        }
+        self.panic_mode = true;
+        self.diagnostics
+            .push(Diagnostic::error(code, message, span));
+    }
+
+    // Skips tokens until a statement boundary, i.e. after a semicolon or before a keyword
+    // starting a statement
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while !self.check(Token::EOF) {
+            if matches!(self.last_token, Token::Semicolon) {
+                return;
+            }
+            match self.prev_token {
+                Token::Class
+                | Token::Fun
+                | Token::Var
+                | Token::For
+                | Token::If
+                | Token::While
+                | Token::Print
+                | Token::Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    /// Parses the whole input, declarations with errors are left out of the returned AST
+    pub fn parse_root(&mut self) -> (Ast, Vec<Diagnostic>) {
+        // Set up initial state
+        self.advance();
+        self.advance();
+
+        let mut stmts = vec![];
+        while !self.check(Token::EOF) {
+            stmts.extend(self.parse_declaration());
+        }
+
+        let ast = Ast {
+            stmts,
+            end: self.prev_span,
+        };
+        (ast, mem::take(&mut self.diagnostics))
+    }
+
+    // Span from the start of a node to the token consumed last
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.last_span)
+    }
+
+    fn parse_declaration(&mut self) -> Option<Stmt> {
+        let start = self.prev_span;
+        let kind = if self.eat(Token::Var) {
+            self.parse_var_decl()
+        } else if self.eat(Token::Fun) {
+            self.parse_fun_decl()
+        } else if self.eat(Token::Class) {
+            self.parse_class_decl()
+        } else {
+            self.parse_statement().kind
+        };
+
+        // The rest of a declaration with errors is skipped, parsing resumes at the next one
+        // The declaration is dropped, so compiling the others reports no errors following from it
+        if self.panic_mode {
+            self.synchronize();
+            return None;
+        }
+
+        Some(Stmt {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    // Returns false if the limit is reached, the rest of the statement is skipped then and the
+    // caller returns a placeholder instead of parsing further
+    fn enter_nesting(&mut self, message: &str) -> bool {
+        if self.nesting == MAX_NESTING {
+            self.error(Code::NestingTooDeep, message);
+            self.too_deep = true;
+            while !self.check(Token::Semicolon) && !self.check(Token::EOF) {
+                self.advance();
             }
+            return false;
+        }
+        self.nesting += 1;
+        true
+    }
+
+    fn leave_nesting(&mut self) {
+        self.nesting -= 1;
+        if self.nesting == 0 {
+            self.too_deep = false;
+        }
+    }
+
+    fn parse_var_decl(&mut self) -> StmtKind {
+        let ident = self.parse_ident("Expect variable name.");
+
+        let init = if self.eat(Token::Equal) {
+            Some(self.parse_expression(Precedence::None))
         } else {
-            self.token = Token::EOF
+            None
+        };
+        self.expect(Token::Semicolon, "Expect ';' after variable declaration.");
+
+        StmtKind::Var(ident, init)
+    }
+
+    fn parse_class_decl(&mut self) -> StmtKind {
+        let name = self.parse_ident("Expect class name.");
+        let superclass = if self.eat(Token::Less) {
+            Some(self.parse_ident("Expect superclass name."))
+        } else {
+            None
+        };
+        self.expect(Token::LBrace, "Expect '{' before class body.");
+
+        let mut methods = vec![];
+        while !self.check(Token::RBrace) && !self.check(Token::EOF) {
+            let name = self.parse_ident("Expect method name.");
+            methods.push(self.parse_function(name));
         }
+        self.expect(Token::RBrace, "Expect '}' after class body.");
+
+        StmtKind::Class(ClassDecl {
+            name,
+            superclass,
+            methods,
+        })
     }
 
-    pub fn error(&mut self, message: &str) {
-        self.error_at(self.prev_line, self.prev_slice, message);
+    fn parse_fun_decl(&mut self) -> StmtKind {
+        let name = self.parse_ident("Expect function name.");
+        StmtKind::Fun(self.parse_function(name))
     }
 
-    pub fn error_at_current(&mut self, message: &str) {
-        self.error_at(self.lexer.extras.0, self.lexer.slice(), message);
+    // Parses the parameters and body following the name of a function or method
+    fn parse_function(&mut self, name: Ident) -> FunDecl {
+        if !self.enter_nesting("Function nesting too deep.") {
+            return FunDecl {
+                name,
+                params: vec![],
+                body: vec![],
+                end: self.prev_span,
+            };
+        }
+        self.expect(Token::LParen, "Expect '(' after function name.");
+
+        let mut params = vec![];
+        if !self.check(Token::RParen) {
+            loop {
+                if params.len() == MAX_ARGS {
+                    self.error(
+                        Code::TooManyArguments,
+                        &format!("Can't have more than {MAX_ARGS} parameters."),
+                    );
+                }
+                params.push(self.parse_ident("Expect parameter name."));
+                if !self.eat(Token::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen, "Expect ')' after parameters.");
+
+        self.expect(Token::LBrace, "Expect '{' before function body.");
+        let body = self.parse_block_stmts();
+
+        self.leave_nesting();
+        FunDecl {
+            name,
+            params,
+            body,
+            end: self.last_span,
+        }
     }
 
-    pub fn error_at(&mut self, line: usize, lexeme: &str, message: &str) {
-        eprint!("[line {}] Error", line);
+    fn parse_ident(&mut self, message: &str) -> Ident {
+        match self.prev_token {
+            Token::Identifier(span) => {
+                let name = span.string(self.lexer.source()).to_owned();
+                let span = self.prev_span;
+                self.advance();
+                Ident { name, span }
+            }
+            _ => {
+                self.error(Code::ExpectedToken, message);
+                Ident {
+                    name: String::new(),
+                    span: self.prev_span,
+                }
+            }
+        }
+    }
 
-        if self.at_end {
-            eprint!(" at end");
+    fn parse_statement(&mut self) -> Stmt {
+        let start = self.prev_span;
+        if !self.enter_nesting("Statement nesting too deep.") {
+            return Stmt {
+                kind: StmtKind::Block(vec![]),
+                span: start,
+            };
+        }
+        let kind = if self.eat(Token::Print) {
+            self.parse_print_stmt()
+        } else if self.eat(Token::LBrace) {
+            self.parse_block()
+        } else if self.eat(Token::If) {
+            self.parse_if_stmt()
+        } else if self.eat(Token::While) {
+            self.parse_while_stmt()
+        } else if self.eat(Token::For) {
+            self.parse_for_stmt()
+        } else if self.check(Token::Return) {
+            self.parse_return_stmt()
         } else {
-            eprint!(" at {}", lexeme)
+            self.parse_expr_stmt()
+        };
+
+        self.leave_nesting();
+        Stmt {
+            kind,
+            span: self.span_from(start),
         }
-        eprintln!(": {message}");
-        self.had_error = true;
     }
 
-    pub fn parse_root(&mut self) -> Result<Ast, ()> {
-        // Set up initial state
-        self.advance();
+    fn parse_block(&mut self) -> StmtKind {
+        StmtKind::Block(self.parse_block_stmts())
+    }
+
+    // Parses the declarations up to and including the closing brace
+    fn parse_block_stmts(&mut self) -> Vec<Stmt> {
+        let mut stmts = vec![];
+        while !self.check(Token::RBrace) && !self.check(Token::EOF) {
+            stmts.extend(self.parse_declaration());
+        }
+        self.expect(Token::RBrace, "Expect '}' after block.");
+        stmts
+    }
+
+    fn parse_if_stmt(&mut self) -> StmtKind {
+        self.expect(Token::LParen, "Expect '(' after 'if'.");
+        let cond = self.parse_expression(Precedence::None);
+        self.expect(Token::RParen, "Expect ')' after condition.");
+
+        let then_branch = Box::new(self.parse_statement());
+        let else_branch = if self.eat(Token::Else) {
+            Some(Box::new(self.parse_statement()))
+        } else {
+            None
+        };
+
+        StmtKind::If(cond, then_branch, else_branch)
+    }
+
+    fn parse_while_stmt(&mut self) -> StmtKind {
+        self.expect(Token::LParen, "Expect '(' after 'while'.");
+        let cond = self.parse_expression(Precedence::None);
+        self.expect(Token::RParen, "Expect ')' after condition.");
+        let body = Box::new(self.parse_statement());
+
+        StmtKind::While(cond, body)
+    }
+
+    fn parse_for_stmt(&mut self) -> StmtKind {
+        self.expect(Token::LParen, "Expect '(' after 'for'.");
+        let start = self.prev_span;
+        let init = if self.eat(Token::Semicolon) {
+            None
+        } else {
+            let kind = if self.eat(Token::Var) {
+                self.parse_var_decl()
+            } else {
+                self.parse_expr_stmt()
+            };
+            Some(Box::new(Stmt {
+                kind,
+                span: self.span_from(start),
+            }))
+        };
+
+        let cond = if self.check(Token::Semicolon) {
+            None
+        } else {
+            Some(self.parse_expression(Precedence::None))
+        };
+        self.expect(Token::Semicolon, "Expect ';' after loop condition.");
+
+        let incr = if self.check(Token::RParen) {
+            None
+        } else {
+            Some(self.parse_expression(Precedence::None))
+        };
+        self.expect(Token::RParen, "Expect ')' after for clauses.");
+
+        let body = Box::new(self.parse_statement());
+
+        StmtKind::For(init, cond, incr, body)
+    }
+
+    fn parse_return_stmt(&mut self) -> StmtKind {
+        let keyword = self.prev_span;
         self.advance();
+
+        let value = if self.check(Token::Semicolon) {
+            None
+        } else {
+            Some(self.parse_expression(Precedence::None))
+        };
+        self.expect(Token::Semicolon, "Expect ';' after return value.");
+
+        StmtKind::Return(keyword, value)
+    }
+
+    fn parse_print_stmt(&mut self) -> StmtKind {
         let expr = self.parse_expression(Precedence::None);
-        let ast = Ast { root: expr };
-        Ok(ast)
+        self.expect(Token::Semicolon, "Expect ';' after value.");
+        StmtKind::Print(expr)
+    }
+
+    fn parse_expr_stmt(&mut self) -> StmtKind {
+        let expr = self.parse_expression(Precedence::None);
+        self.expect(Token::Semicolon, "Expect ';' after expression.");
+        StmtKind::Expr(expr)
     }
 
     fn parse_expression(&mut self, precedence: Precedence) -> Expr {
-        let mut left = self.parse_prefix();
+        // Only an expression parsed at the lowest precedence may be an assignment target
+        let can_assign = precedence <= Precedence::Assignment;
+        let start = self.prev_span;
+        if !self.enter_nesting("Expression nesting too deep.") {
+            return Expr {
+                kind: ExprKind::Lit(Lit { kind: LitKind::Nil }),
+                span: start,
+            };
+        }
+        let kind = self.parse_prefix(can_assign);
+        let mut left = Expr {
+            kind,
+            span: self.span_from(start),
+        };
 
-        let token_precedence = Precedence::from_token(&self.prev_token);
+        let mut token_precedence = Precedence::from_token(&self.prev_token);
 
+        // Every infix expression wraps the one before it, so long chains nest as deeply
+        let mut infix_nesting = 0;
         while precedence < token_precedence {
-            left = self.parse_infix(left, token_precedence);
+            if !self.enter_nesting("Expression nesting too deep.") {
+                break;
+            }
+            infix_nesting += 1;
+            // Infix expressions start with their left operand
+            let kind = self.parse_infix(left, token_precedence, can_assign);
+            left = Expr {
+                kind,
+                span: self.span_from(start),
+            };
+            token_precedence = Precedence::from_token(&self.prev_token);
+        }
+
+        if can_assign && self.check(Token::Equal) {
+            self.error(Code::InvalidAssignmentTarget, "Invalid assignment target.");
+        }
+        for _ in 0..infix_nesting {
+            self.leave_nesting();
         }
-        return left;
+        self.leave_nesting();
+        left
     }
 
-    fn parse_prefix(&mut self) -> Expr {
+    fn parse_prefix(&mut self, can_assign: bool) -> ExprKind {
         match self.prev_token {
-            Token::Number(_) => self.parse_num_literal(),
-            default => unimplemented!("Unimplemented: {:?}", default),
+            Token::Number(num) => self.parse_literal(Lit::from(num)),
+            Token::String(span) => self.parse_str_literal(span),
+            Token::True => self.parse_literal(Lit::from(true)),
+            Token::False => self.parse_literal(Lit::from(false)),
+            Token::Nil => self.parse_literal(Lit { kind: LitKind::Nil }),
+            Token::Minus => self.parse_unary(UnOp::Neg),
+            Token::Bang => self.parse_unary(UnOp::Not),
+            Token::LParen => self.parse_grouping(),
+            Token::Identifier(_) => self.parse_variable(can_assign),
+            Token::This => self.parse_this(),
+            Token::Super => self.parse_super(),
+            _ => self.missing_expression(),
         }
     }
 
-    fn parse_infix(&mut self, left: Expr, precedence: Precedence) -> Expr {
-        match self.prev_token {
-            Token::Plus | Token::Minus | Token::Star | Token::Slash => {
-                self.parse_binop(left, precedence)
-            }
-            _ => unimplemented!("Unimplemented infix for: {:?}", self.prev_token),
+    // Stands in for the expression, the token is skipped so parsing always makes progress
+    fn missing_expression(&mut self) -> ExprKind {
+        self.error(Code::ExpectedExpression, "Expect expression.");
+        if !self.check(Token::EOF) {
+            self.advance();
         }
+        ExprKind::Lit(Lit { kind: LitKind::Nil })
     }
 
-    fn parse_binop(&mut self, left: Expr, precedence: Precedence) -> Expr {
-        let lhs = Box::new(left);
+    fn parse_infix(&mut self, left: Expr, precedence: Precedence, can_assign: bool) -> ExprKind {
         let op = match self.prev_token {
             Token::Plus => BinOpKind::Add,
             Token::Minus => BinOpKind::Sub,
             Token::Star => BinOpKind::Mul,
             Token::Slash => BinOpKind::Div,
-            other => unimplemented!("Unimplemented Binops: {}", format!("{:?}", other)),
+            Token::EqualEqual => BinOpKind::Eq,
+            Token::BangEqual => BinOpKind::Ne,
+            Token::Less => BinOpKind::Lt,
+            Token::LessEqual => BinOpKind::Le,
+            Token::Greater => BinOpKind::Gt,
+            Token::GreaterEqual => BinOpKind::Ge,
+            Token::And => BinOpKind::And,
+            Token::Or => BinOpKind::Or,
+            Token::LParen => return self.parse_call(left),
+            Token::Dot => return self.parse_dot(left, can_assign),
+            // Only tokens with a precedence continue an expression, so this is never reached
+            _ => {
+                self.error(Code::ExpectedExpression, "Expect expression.");
+                return left.kind;
+            }
         };
+        self.parse_binop(left, op, precedence)
+    }
 
+    fn parse_binop(&mut self, left: Expr, op: BinOpKind, precedence: Precedence) -> ExprKind {
+        let lhs = Box::new(left);
         self.advance();
         let rhs = Box::new(self.parse_expression(precedence));
-        let expr = Expr {
-            kind: ExprKind::Binary(op, lhs, rhs),
+
+        ExprKind::Binary(op, lhs, rhs)
+    }
+
+    fn parse_call(&mut self, callee: Expr) -> ExprKind {
+        self.advance();
+
+        let mut args = vec![];
+        if !self.check(Token::RParen) {
+            loop {
+                let arg = self.parse_expression(Precedence::None);
+                if args.len() == MAX_ARGS {
+                    self.error(
+                        Code::TooManyArguments,
+                        &format!("Can't have more than {MAX_ARGS} arguments."),
+                    );
+                }
+                args.push(arg);
+                if !self.eat(Token::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen, "Expect ')' after arguments.");
+
+        ExprKind::Call(Box::new(callee), args)
+    }
+
+    fn parse_dot(&mut self, object: Expr, can_assign: bool) -> ExprKind {
+        self.advance();
+        let name = self.parse_ident("Expect property name after '.'.");
+        let object = Box::new(object);
+
+        if can_assign && self.eat(Token::Equal) {
+            let value = Box::new(self.parse_expression(Precedence::None));
+            return ExprKind::Set(object, name, value);
+        }
+
+        ExprKind::Get(object, name)
+    }
+
+    fn parse_this(&mut self) -> ExprKind {
+        let ident = Ident {
+            name: "this".to_owned(),
+            span: self.prev_span,
         };
-        expr
+        self.advance();
+
+        ExprKind::This(ident)
     }
 
-    pub fn parse_num_literal(&mut self) -> Expr {
-        let num = match &self.prev_token {
-            Token::Number(num) => num.clone(),
-            _ => panic!(
-                "Unexpected token instead of number: {}",
-                format!("{:?}", self.token)
-            ),
+    fn parse_super(&mut self) -> ExprKind {
+        let keyword = Ident {
+            name: "super".to_owned(),
+            span: self.prev_span,
         };
         self.advance();
+        self.expect(Token::Dot, "Expect '.' after 'super'.");
+        let method = self.parse_ident("Expect superclass method name.");
+
+        ExprKind::Super(keyword, method)
+    }
+
+    // Numbers and keywords, whose token already holds the value
+    fn parse_literal(&mut self, literal: Lit) -> ExprKind {
+        self.advance();
+        ExprKind::Lit(literal)
+    }
+
+    fn parse_unary(&mut self, op: UnOp) -> ExprKind {
+        self.advance();
+
+        let operand = Box::new(self.parse_expression(Precedence::Unary));
+        ExprKind::Unary(op, operand)
+    }
+
+    fn parse_grouping(&mut self) -> ExprKind {
+        self.advance();
+        let inner = Box::new(self.parse_expression(Precedence::None));
+        self.expect(Token::RParen, "Expect ')' after expression.");
+
+        ExprKind::Paren(inner)
+    }
+
+    fn parse_str_literal(&mut self, span: TokenSpan) -> ExprKind {
+        let quoted = span.string(self.lexer.source());
+        let value = quoted[1..quoted.len() - 1].to_owned();
+        self.advance();
+
+        ExprKind::Lit(Lit {
+            kind: LitKind::Str(value),
+        })
+    }
+
+    fn parse_variable(&mut self, can_assign: bool) -> ExprKind {
+        let ident = self.parse_ident("Expect variable name.");
+
+        if can_assign && self.eat(Token::Equal) {
+            let value = Box::new(self.parse_expression(Precedence::None));
+            return ExprKind::Assign(ident, value);
+        }
 
-        let literal = Lit::from(num);
-        let kind = ExprKind::Lit(literal);
-        let expr = Expr { kind: kind };
-        expr
+        ExprKind::Variable(ident)
     }
 }
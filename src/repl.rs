@@ -1,25 +1,76 @@
 use std::io::{self, Write};
 
-use crate::vm::VM;
+use crate::{compiler::DebugOptions, diagnostics::Renderer, vm::VM};
 
-pub fn repl() {
+const HELP: &str = "\
+:tokens  toggle printing the token stream
+:ast     toggle printing the syntax tree
+:disasm  toggle printing the compiled bytecode
+:trace   toggle tracing executed instructions
+:help    show this message";
+
+pub fn repl(options: DebugOptions, renderer: Renderer) {
     let mut vm = VM::default();
+    vm.set_debug_options(options);
+    vm.set_renderer(renderer);
     loop {
         print!("> ");
         io::stdout().flush().expect("Failed to flush io");
 
         let mut line = String::new();
 
-        io::stdin()
+        let read = io::stdin()
             .read_line(&mut line)
             .expect("Failed to read line");
 
+        // End of input, e.g. Ctrl-D
+        if read == 0 {
+            println!();
+            return;
+        }
+
         // Fix for '\n' in command line
         let line = line.trim();
 
-        let res = vm.interpret(&line);
-        if let Err(e) = res {
-            eprintln!("VM Error: {:?}", e);
+        if line.starts_with(':') {
+            run_command(&mut vm, line);
+            continue;
         }
+
+        // Errors are already reported by the VM, the session just continues
+        let _ = vm.interpret(line);
     }
 }
+
+// Commands toggle the debug options of the running session
+fn run_command(vm: &mut VM, command: &str) {
+    let mut options = vm.debug_options();
+    let (name, enabled) = match command {
+        ":tokens" => {
+            options.print_tokens = !options.print_tokens;
+            ("Token printing", options.print_tokens)
+        }
+        ":ast" => {
+            options.print_ast = !options.print_ast;
+            ("AST printing", options.print_ast)
+        }
+        ":disasm" => {
+            options.print_disassembly = !options.print_disassembly;
+            ("Disassembly printing", options.print_disassembly)
+        }
+        ":trace" => {
+            options.trace_execution = !options.trace_execution;
+            ("Execution tracing", options.trace_execution)
+        }
+        ":help" => {
+            println!("{HELP}");
+            return;
+        }
+        other => {
+            eprintln!("Unknown command {other}, try :help");
+            return;
+        }
+    };
+    vm.set_debug_options(options);
+    println!("{name} {}", if enabled { "enabled" } else { "disabled" });
+}
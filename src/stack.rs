@@ -9,9 +9,11 @@ pub struct Sp<const STACK_SIZE: usize> {
 }
 
 impl<const STACK_SIZE: usize> Sp<STACK_SIZE> {
+    /// # Safety
+    /// The pointer is only valid as long as `stack` is neither moved nor dropped.
     pub unsafe fn create(stack: &mut Stack<STACK_SIZE>) -> Sp<STACK_SIZE> {
         let ptr = stack.bytes.as_mut_ptr();
-        assert!(ptr != std::ptr::null_mut());
+        assert!(!ptr.is_null());
         Sp {
             ptr: NonNull::new(ptr).unwrap(),
         }
@@ -26,7 +28,7 @@ impl<const STACK_SIZE: usize> Sp<STACK_SIZE> {
 
     #[inline(always)]
     pub fn write_value(&mut self, value: &LoxValue) {
-        unsafe { *self.ptr.as_mut() = value.clone() };
+        unsafe { *self.ptr.as_mut() = *value };
     }
 
     #[inline(always)]
@@ -42,7 +44,7 @@ impl<const STACK_SIZE: usize> Sp<STACK_SIZE> {
 
 impl<const STACK_SIZE: usize> PartialEq for Sp<STACK_SIZE> {
     fn eq(&self, other: &Self) -> bool {
-        return self.ptr == other.ptr;
+        self.ptr == other.ptr
     }
 }
 
@@ -63,6 +65,12 @@ pub struct Stack<const STACK_SIZE: usize> {
     bytes: Pin<Box<[LoxValue; STACK_SIZE]>>,
 }
 
+impl<const STACK_SIZE: usize> Default for Stack<STACK_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<const STACK_SIZE: usize> Stack<STACK_SIZE> {
     pub fn new() -> Stack<STACK_SIZE> {
         let heap_vec = vec![LoxValue::default(); STACK_SIZE];
@@ -76,6 +84,13 @@ impl<const STACK_SIZE: usize> Stack<STACK_SIZE> {
         unsafe { Sp::<STACK_SIZE>::create(self) }
     }
 
+    // One past the last slot, the stack is full once the stack pointer reaches it
+    pub fn get_end_sp(&mut self) -> Sp<STACK_SIZE> {
+        let mut end = self.get_base_sp();
+        end.inc(STACK_SIZE);
+        end
+    }
+
     pub fn get_stack_iterator(&mut self, up_to: Sp<STACK_SIZE>) -> StackIterator<STACK_SIZE> {
         StackIterator::new(self, &up_to)
     }
@@ -91,10 +106,7 @@ impl<const STACK_SIZE: usize> StackIterator<STACK_SIZE> {
         let base = stack.get_base_sp();
         let top = sp.clone();
 
-        Self {
-            curr: base,
-            top: top,
-        }
+        Self { curr: base, top }
     }
 }
 
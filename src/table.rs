@@ -0,0 +1,228 @@
+/*
+Open addressing hash table keyed by interned strings.
+Collisions are resolved with linear probing, deleted entries leave tombstones behind
+so probe sequences stay intact. The hash of a key is cached in its string object,
+so neither lookups nor growing ever rehash string contents.
+*/
+
+use std::{fmt, mem};
+
+use crate::{lox_value::LoxValue, object::ObjRef};
+
+const MAX_LOAD: f64 = 0.75;
+const MIN_CAPACITY: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default)]
+enum Entry {
+    #[default]
+    Empty,
+    // Marks a removed entry, probing continues past it
+    Tombstone,
+    Occupied(ObjRef, LoxValue),
+}
+
+#[derive(Default)]
+pub struct Table {
+    entries: Box<[Entry]>,
+    // Occupied entries and tombstones, both count towards the load factor
+    count: usize,
+    len: usize,
+}
+
+/// FNV-1a, computed once when a string is created
+pub fn hash_str(value: &str) -> u32 {
+    let mut hash: u32 = 2_166_136_261;
+    for byte in value.bytes() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(16_777_619);
+    }
+    hash
+}
+
+fn key_hash(key: ObjRef) -> u32 {
+    key.as_string().expect("Table keys must be strings").hash
+}
+
+impl Table {
+    pub fn new() -> Table {
+        Table {
+            entries: Box::new([]),
+            count: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Memory owned by the table, used by the heap to decide when to collect garbage
+    pub fn allocated_bytes(&self) -> usize {
+        self.capacity() * mem::size_of::<Entry>()
+    }
+
+    pub fn get(&self, key: ObjRef) -> Option<LoxValue> {
+        if self.len == 0 {
+            return None;
+        }
+        match self.entries[self.find_slot(key)] {
+            Entry::Occupied(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: ObjRef) -> Option<&mut LoxValue> {
+        if self.len == 0 {
+            return None;
+        }
+        let slot = self.find_slot(key);
+        match &mut self.entries[slot] {
+            Entry::Occupied(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: ObjRef) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns true if the key was not present before
+    pub fn insert(&mut self, key: ObjRef, value: LoxValue) -> bool {
+        if (self.count + 1) as f64 > self.capacity() as f64 * MAX_LOAD {
+            let capacity = (self.capacity() * 2).max(MIN_CAPACITY);
+            self.grow(capacity);
+        }
+
+        let slot = self.find_slot(key);
+        let entry = &mut self.entries[slot];
+        let is_new = !matches!(entry, Entry::Occupied(..));
+        // Reusing a tombstone does not change the load
+        if matches!(entry, Entry::Empty) {
+            self.count += 1;
+        }
+        if is_new {
+            self.len += 1;
+        }
+        *entry = Entry::Occupied(key, value);
+        is_new
+    }
+
+    /// Returns the removed value, if the key was present
+    pub fn remove(&mut self, key: ObjRef) -> Option<LoxValue> {
+        if self.len == 0 {
+            return None;
+        }
+        let slot = self.find_slot(key);
+        match self.entries[slot] {
+            Entry::Occupied(_, value) => {
+                self.entries[slot] = Entry::Tombstone;
+                self.len -= 1;
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Copies every entry of `other` into this table, overwriting existing keys
+    pub fn extend_from(&mut self, other: &Table) {
+        for (key, value) in other.iter() {
+            self.insert(key, value);
+        }
+    }
+
+    /// Looks up a key by its contents, which is how strings are interned in the first place
+    pub fn find_string(&self, value: &str, hash: u32) -> Option<ObjRef> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let mask = self.capacity() - 1;
+        let mut index = hash as usize & mask;
+        loop {
+            match self.entries[index] {
+                Entry::Empty => return None,
+                Entry::Tombstone => {}
+                Entry::Occupied(key, _) => {
+                    let string = key.as_string().expect("Table keys must be strings");
+                    if string.hash == hash && *string.value == *value {
+                        return Some(key);
+                    }
+                }
+            }
+            index = (index + 1) & mask;
+        }
+    }
+
+    /// Removes every entry for which `keep` returns false
+    pub fn retain(&mut self, mut keep: impl FnMut(ObjRef, LoxValue) -> bool) {
+        for entry in self.entries.iter_mut() {
+            if let Entry::Occupied(key, value) = *entry {
+                if !keep(key, value) {
+                    *entry = Entry::Tombstone;
+                    self.len -= 1;
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ObjRef, LoxValue)> + '_ {
+        self.entries.iter().filter_map(|entry| match *entry {
+            Entry::Occupied(key, value) => Some((key, value)),
+            _ => None,
+        })
+    }
+
+    // Returns the slot holding the key, or the slot where it should be inserted
+    // The capacity is a power of two and the table is never full, so probing terminates
+    fn find_slot(&self, key: ObjRef) -> usize {
+        let mask = self.capacity() - 1;
+        let mut index = key_hash(key) as usize & mask;
+        let mut tombstone = None;
+
+        loop {
+            match self.entries[index] {
+                // Prefer reusing the first tombstone on the probe sequence
+                Entry::Empty => return tombstone.unwrap_or(index),
+                Entry::Tombstone => {
+                    tombstone.get_or_insert(index);
+                }
+                Entry::Occupied(occupant, _) if occupant == key => return index,
+                Entry::Occupied(..) => {}
+            }
+            index = (index + 1) & mask;
+        }
+    }
+
+    // Rehashes every live entry, tombstones are dropped in the process
+    fn grow(&mut self, capacity: usize) {
+        let old_entries = std::mem::replace(
+            &mut self.entries,
+            vec![Entry::Empty; capacity].into_boxed_slice(),
+        );
+        self.count = 0;
+        self.len = 0;
+
+        for entry in old_entries.iter() {
+            if let Entry::Occupied(key, value) = *entry {
+                let slot = self.find_slot(key);
+                self.entries[slot] = Entry::Occupied(key, value);
+                self.count += 1;
+                self.len += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
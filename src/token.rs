@@ -1,6 +1,6 @@
-use std::num::ParseFloatError;
+use std::fmt;
 
-use logos::{Logos, Skip};
+use logos::Logos;
 
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub struct TokenSpan {
@@ -14,23 +14,93 @@ impl TokenSpan {
     }
 }
 
+/// Location of a piece of source code, a byte range along with the line and column it starts at
+/// Lines and columns start at 1, columns count characters
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Span of the token the lexer produced last
+    pub fn of_token(lex: &logos::Lexer<Token>) -> Span {
+        let range = lex.span();
+        let source = lex.source();
+        // Multi-line strings already advanced the line counter past their start
+        let newlines = lex.slice().matches('\n').count();
+        let line_start = if newlines == 0 {
+            lex.extras.line_start
+        } else {
+            source[..range.start].rfind('\n').map_or(0, |newline| newline + 1)
+        };
+
+        Span {
+            start: range.start,
+            end: range.end,
+            line: lex.extras.line - newlines,
+            column: source[line_start..range.start].chars().count() + 1,
+        }
+    }
+
+    /// Span from the start of this one to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+/// Position of the lexer, used to locate tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineTracker {
+    pub line: usize,
+    // Byte offset of the first character of the current line
+    pub line_start: usize,
+}
+
+impl Default for LineTracker {
+    fn default() -> Self {
+        Self {
+            line: 1,
+            line_start: 0,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq)]
 pub enum LexingError {
+    // Produced by logos for input no token starts with, `next_token` replaces it with
+    // `UnexpectedCharacter` while the lexer is still positioned at the character
     #[default]
     Unspecified,
-    InvalidNumber,
+    InvalidNumber(TokenSpan),
+    // A string without its closing quote, spanning from the opening quote to the end of input
+    UnterminatedString(TokenSpan),
+    UnexpectedCharacter(char, TokenSpan),
 }
 
-impl From<ParseFloatError> for LexingError {
-    fn from(_: ParseFloatError) -> Self {
-        LexingError::InvalidNumber
+// Messages follow clox
+impl fmt::Display for LexingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexingError::Unspecified => write!(f, "Invalid token."),
+            LexingError::InvalidNumber(_) => write!(f, "Invalid number."),
+            LexingError::UnterminatedString(_) => write!(f, "Unterminated string."),
+            LexingError::UnexpectedCharacter(_, _) => write!(f, "Unexpected character."),
+        }
     }
 }
 
 #[derive(Logos, Debug, PartialEq, Clone, Copy)]
 #[logos(error = LexingError)]
-#[logos(extras = (usize, usize))]
-#[logos(skip r"[ \t\f]+")] // Ignore this regex pattern between tokens
+#[logos(extras = LineTracker)]
+#[logos(skip r"[ \t\r\f]+")] // Ignore this regex pattern between tokens
+#[logos(skip r"//[^\n]*")] // Line comments
+#[logos(skip(r"\n", newline))] // Counted to locate tokens
 pub enum Token {
     // Single character tokens
     #[token("+")]
@@ -79,6 +149,7 @@ pub enum Token {
     #[regex("[a-zA-Z_][a-zA-Z0-9_]*", identifier)]
     Identifier(TokenSpan),
     #[regex(r#""[^"]*""#, string)]
+    #[regex(r#""[^"]*"#, unterminated_string)]
     String(TokenSpan),
     #[regex(r"\d+(\.\d+)?", number)]
     Number(f64),
@@ -108,18 +179,33 @@ pub enum Token {
     Return,
     #[token("super")]
     Super,
+    #[token("this")]
+    This,
+    #[token("true")]
+    True,
     #[token("var")]
     Var,
     #[token("while")]
     While,
 
-    //For error reporting
-    #[regex(r"\n", newline)]
-    Newline,
-
     EOF,
 }
 
+/// Next token of the lexer, every error carries the span of the input it rejects
+pub fn next_token(lex: &mut logos::Lexer<Token>) -> Option<Result<Token, LexingError>> {
+    match lex.next()? {
+        // The rejected input is still the current slice of the lexer
+        Err(LexingError::Unspecified) => {
+            let character = lex.slice().chars().next().expect("Rejected input is not empty");
+            Some(Err(LexingError::UnexpectedCharacter(
+                character,
+                identifier(lex),
+            )))
+        }
+        token => Some(token),
+    }
+}
+
 fn identifier(lex: &mut logos::Lexer<Token>) -> TokenSpan {
     // Length of source code is guaranteed to be less than u32::MAX
     TokenSpan {
@@ -130,6 +216,8 @@ fn identifier(lex: &mut logos::Lexer<Token>) -> TokenSpan {
 
 #[allow(clippy::cast_possible_truncation)]
 fn string(lex: &mut logos::Lexer<Token>) -> TokenSpan {
+    // Strings may span multiple lines
+    count_newlines(lex);
     // Length of source code is guaranteed to be less than u32::MAX
     TokenSpan {
         start: lex.span().start as u32,
@@ -137,12 +225,27 @@ fn string(lex: &mut logos::Lexer<Token>) -> TokenSpan {
     }
 }
 
+// Without a closing quote the string runs until the end of the input
+fn unterminated_string(lex: &mut logos::Lexer<Token>) -> Result<TokenSpan, LexingError> {
+    let span = string(lex);
+    Err(LexingError::UnterminatedString(span))
+}
+
 fn number(lex: &mut logos::Lexer<Token>) -> Result<f64, LexingError> {
-    lex.slice().parse().map_err(|_| LexingError::InvalidNumber)
+    lex.slice()
+        .parse()
+        .map_err(|_| LexingError::InvalidNumber(identifier(lex)))
 }
 
-fn newline(lex: &mut logos::Lexer<Token>) -> Skip {
-    lex.extras.0 += 1;
-    lex.extras.1 = lex.span().end;
-    Skip
+fn newline(lex: &mut logos::Lexer<Token>) {
+    lex.extras.line += 1;
+    lex.extras.line_start = lex.span().end;
+}
+
+fn count_newlines(lex: &mut logos::Lexer<Token>) {
+    let slice = lex.slice();
+    if let Some(last) = slice.rfind('\n') {
+        lex.extras.line += slice.matches('\n').count();
+        lex.extras.line_start = lex.span().start + last + 1;
+    }
 }
@@ -1,81 +1,560 @@
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
 use crate::{
     bytecode::{Bytecode, Ip},
-    compiler::Compiler,
+    compiler::{Compiler, DebugOptions},
+    diagnostics::{Code, Diagnostic, Renderer},
+    heap::Heap,
     lox_value::LoxValue,
+    object::{
+        ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjKind, ObjRef,
+        ObjUpvalue,
+    },
     opcodes::Op,
     stack::{Sp, Stack},
+    table::Table,
+    token::Span,
 };
 
 #[derive(Debug, Clone, Copy)]
 pub enum Error {
     Compile,
     Runtime,
+    /// The script could not be read, or the output could not be written
+    Io,
+}
+
+impl From<io::Error> for Error {
+    fn from(_: io::Error) -> Self {
+        Error::Io
+    }
+}
+
+impl Error {
+    // Exit codes follow clox, which borrows them from sysexits.h
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Error::Compile => 65,
+            Error::Runtime => 70,
+            Error::Io => 74,
+        }
+    }
 }
 
-pub const STACK_SIZE: usize = 10;
+pub const FRAMES_MAX: usize = 64;
+// Every frame can address up to 256 slots
+pub const STACK_SIZE: usize = FRAMES_MAX * (u8::MAX as usize + 1);
 
+// An ongoing function call
 #[derive(Debug)]
+struct CallFrame {
+    closure: ObjRef,
+    ip: Ip,
+    // First slot of the frame, holding the called function followed by its arguments
+    slots: Sp<STACK_SIZE>,
+}
+
+impl CallFrame {
+    fn closure(&self) -> &ObjClosure {
+        self.closure.as_closure().expect("Only closures are called")
+    }
+
+    fn bytecode(&self) -> &Bytecode {
+        &self.closure().function().bytecode
+    }
+}
+
 pub struct VM {
-    ip: Option<Ip>,
+    frames: Vec<CallFrame>,
     sp: Option<Sp<STACK_SIZE>>,
-    // FIXME: Make more rusty
-    bytecode: Option<Bytecode>,
     stack: Stack<STACK_SIZE>,
+    stack_end: Sp<STACK_SIZE>,
+    // Upvalues still pointing into the stack, ordered by their slot
+    open_upvalues: Vec<ObjRef>,
+    // Outlives a single call to interpret, so the REPL keeps its variables
+    // Keyed by interned names, so lookups hash the pointer instead of the contents
+    globals: Table,
+    heap: Heap,
+    // Interned once, as every instantiation looks up the initializer
+    init_string: ObjRef,
+    // Program output, i.e. everything the Lox code prints
+    out: Box<dyn Write>,
+    // Compile errors, runtime errors and debug dumps
+    diagnostics: Box<dyn Write>,
+    renderer: Renderer,
+    // Rendered by interpret, which has the source the error refers to
+    runtime_error: Option<Diagnostic>,
+    debug_options: DebugOptions,
 }
 
 impl VM {
-    pub fn interpret(&mut self, code: &str) -> Result<(), Error> {
-        let compiler = Compiler::default();
-        let bytecode = compiler.compile(code);
+    /// Creates a VM writing program output to `out` and errors and debug output to `diagnostics`
+    pub fn with_output(out: impl Write + 'static, diagnostics: impl Write + 'static) -> VM {
+        let mut heap = Heap::new();
+        let init_string = heap.intern("init");
+        // The stack is pinned, so its end stays put when the VM is moved
+        let mut stack = Stack::new();
+        let stack_end = stack.get_end_sp();
+        VM {
+            frames: Vec::with_capacity(FRAMES_MAX),
+            sp: None,
+            stack,
+            stack_end,
+            open_upvalues: vec![],
+            globals: Table::new(),
+            heap,
+            init_string,
+            out: Box::new(out),
+            diagnostics: Box::new(diagnostics),
+            renderer: Renderer::default(),
+            runtime_error: None,
+            debug_options: DebugOptions::default(),
+        }
+    }
 
-        self.bytecode = Some(bytecode);
+    pub fn debug_options(&self) -> DebugOptions {
+        self.debug_options
+    }
 
-        let bc = self.bytecode.as_ref().unwrap();
-        self.ip = Some(bc.get_base_ip().unwrap());
+    pub fn set_debug_options(&mut self, options: DebugOptions) {
+        self.debug_options = options;
+    }
 
+    /// Errors are reported in the plain format of clox unless another renderer is set
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn interpret(&mut self, code: &str) -> Result<(), Error> {
+        let compiler = Compiler::new(self.debug_options);
+        let mut dump = String::new();
+        // Nothing runs while compiling, only objects surviving earlier runs are still in use
+        let (globals, init_string) = (&self.globals, self.init_string);
+        let compiled = compiler.compile_with_roots(
+            code,
+            &mut self.heap,
+            &|heap| {
+                heap.mark_table(globals);
+                heap.mark_object(init_string);
+            },
+            &mut dump,
+        );
+        self.diagnostics.write_all(dump.as_bytes())?;
+        let bytecode = compiled.or_else(|errors| {
+            self.report(&errors, code)?;
+            Err(Error::Compile)
+        })?;
+
+        // The script is called like any other function without arguments
+        // The constants of the bytecode are not rooted yet, so the heap is used directly to
+        // not collect garbage until the script is on the stack
+        let function = self.heap.alloc(ObjKind::Function(ObjFunction {
+            arity: 0,
+            upvalue_count: 0,
+            bytecode,
+            name: None,
+        }));
+        let script = self.heap.alloc(ObjKind::Closure(ObjClosure {
+            function,
+            upvalues: vec![],
+        }));
+        self.frames.clear();
+        self.open_upvalues.clear();
         self.sp = Some(self.stack.get_base_sp());
+        self.push(&LoxValue::Obj(script));
+        let result = self.call(script, 0).and_then(|()| self.run());
+        if let Err(Error::Io) = result {
+            self.unwind();
+        }
 
-        loop {
-            let inst = self.ip.as_mut().unwrap().get_op();
-            self.ip.as_mut().unwrap().inc(1);
+        if let Some(error) = self.runtime_error.take() {
+            self.report(&[error], code)?;
+        }
+        result
+    }
 
-            if cfg!(feature = "vm-trace-execution") {
-                println!("          ");
-                for slot in self
-                    .stack
-                    .get_stack_iterator(self.sp.as_mut().unwrap().clone())
-                {
-                    print!("[ {slot} ]");
-                }
-                println!();
+    fn report(&mut self, errors: &[Diagnostic], code: &str) -> io::Result<()> {
+        for error in errors {
+            write!(self.diagnostics, "{}", self.renderer.render(error, code))?;
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        loop {
+            let inst = self.frame().ip.get_op();
+            self.frame_mut().ip.inc(1);
 
-                println!("{inst}");
+            // Temporaries pile up past the 256 slots a frame addresses, but no instruction
+            // pushes more than one value
+            if self.sp.as_ref() == Some(&self.stack_end) {
+                return Err(self.runtime_error(Code::StackOverflow, "Stack overflow."));
+            }
+            if self.debug_options.trace_execution {
+                self.trace_instruction(&inst)?;
             }
             match inst {
                 Op::ConstantSmall => self.op_constant_small(),
-                Op::Add => self.op_add(),
-                Op::Subtract => self.op_subtract(),
-                Op::Multiply => self.op_multiply(),
-                Op::Divide => self.op_divide(),
-                Op::Negate => self.op_negate(),
-                Op::Ret => {
+                Op::Nil => self.push(&LoxValue::Nil),
+                Op::True => self.push(&LoxValue::Bool(true)),
+                Op::False => self.push(&LoxValue::Bool(false)),
+                Op::Add => self.op_add()?,
+                Op::Subtract => self.op_subtract()?,
+                Op::Multiply => self.op_multiply()?,
+                Op::Divide => self.op_divide()?,
+                Op::Negate => self.op_negate()?,
+                Op::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(&LoxValue::Bool(a == b));
+                }
+                Op::Greater => self.op_greater()?,
+                Op::Less => self.op_less()?,
+                Op::Not => {
                     let val = self.pop();
-                    println!("{val}");
-                    return Ok(());
+                    self.push(&LoxValue::Bool(val.is_falsey()));
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Print => self.op_print()?,
+                Op::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip.inc(offset);
+                }
+                Op::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if self.peek().is_falsey() {
+                        self.frame_mut().ip.inc(offset);
+                    }
+                }
+                Op::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip.dec(offset);
+                }
+                Op::GetLocal => self.op_get_local(),
+                Op::SetLocal => self.op_set_local(),
+                Op::DefineGlobal => self.op_define_global(),
+                Op::GetGlobal => self.op_get_global()?,
+                Op::SetGlobal => self.op_set_global()?,
+                Op::GetUpvalue => {
+                    let upvalue = self.read_upvalue();
+                    let value = upvalue.as_upvalue().expect("Closures capture upvalues").get();
+                    self.push(&value);
+                }
+                Op::SetUpvalue => {
+                    let upvalue = self.read_upvalue();
+                    // Assignment is an expression, so the value stays on the stack
+                    let value = self.peek();
+                    upvalue
+                        .as_upvalue()
+                        .expect("Closures capture upvalues")
+                        .set(value);
+                }
+                Op::CloseUpvalue => {
+                    let mut top = self.sp.as_ref().unwrap().clone();
+                    top.dec(1);
+                    self.close_upvalues(&top);
+                    self.pop();
+                }
+                Op::Call => {
+                    let arg_count = self.read_u8() as usize;
+                    self.call_value(self.peek_at(arg_count), arg_count)?;
                 }
+                Op::Closure => self.op_closure(),
+                Op::Invoke => {
+                    let name = self.read_identifier();
+                    let arg_count = self.read_u8() as usize;
+                    self.invoke(name, arg_count)?;
+                }
+                Op::Class => {
+                    let name = self.read_identifier();
+                    let class = self.alloc(ObjKind::Class(ObjClass::new(name)));
+                    self.push(&LoxValue::Obj(class));
+                }
+                Op::Method => self.op_method(),
+                Op::Inherit => self.op_inherit()?,
+                Op::GetSuper => {
+                    let name = self.read_identifier();
+                    let LoxValue::Obj(superclass) = self.pop() else {
+                        unreachable!("The compiler only binds classes to super")
+                    };
+                    self.bind_method(superclass, name)?;
+                }
+                Op::SuperInvoke => {
+                    let name = self.read_identifier();
+                    let arg_count = self.read_u8() as usize;
+                    let LoxValue::Obj(superclass) = self.pop() else {
+                        unreachable!("The compiler only binds classes to super")
+                    };
+                    self.invoke_from_class(superclass, name, arg_count)?;
+                }
+                Op::GetProperty => self.op_get_property()?,
+                Op::SetProperty => self.op_set_property()?,
+                Op::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("Returning from a called function");
+                    // Captured locals outlive the frame, everything else is discarded
+                    self.close_upvalues(&frame.slots);
+                    self.sp = Some(frame.slots);
+                    if self.frames.is_empty() {
+                        self.out.flush()?;
+                        return Ok(());
+                    }
+                    self.push(&result);
+                }
+            }
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("Code only runs inside a call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames
+            .last_mut()
+            .expect("Code only runs inside a call frame")
+    }
+
+    fn call_value(&mut self, callee: LoxValue, arg_count: usize) -> Result<(), Error> {
+        let LoxValue::Obj(obj) = callee else {
+            return Err(
+                self.runtime_error(Code::NotCallable, "Can only call functions and classes.")
+            );
+        };
+
+        match &obj.get().kind {
+            ObjKind::Closure(_) => self.call(obj, arg_count),
+            ObjKind::BoundMethod(bound) => {
+                // The receiver takes the place of the callee, so it ends up in slot zero
+                self.set_at(arg_count, bound.receiver);
+                self.call(bound.method, arg_count)
+            }
+            ObjKind::Class(class) => {
+                let instance = self.alloc(ObjKind::Instance(ObjInstance::new(obj)));
+                self.set_at(arg_count, LoxValue::Obj(instance));
+
+                let initializer = class.methods.borrow().get(self.init_string);
+                match initializer {
+                    Some(LoxValue::Obj(initializer)) => self.call(initializer, arg_count),
+                    _ if arg_count != 0 => Err(self.runtime_error(
+                        Code::ArityMismatch,
+                        &format!("Expected 0 arguments but got {arg_count}."),
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            _ => Err(self.runtime_error(Code::NotCallable, "Can only call functions and classes.")),
+        }
+    }
+
+    fn invoke(&mut self, name: ObjRef, arg_count: usize) -> Result<(), Error> {
+        let receiver = self.peek_at(arg_count);
+        let Some(instance) = receiver.as_instance() else {
+            return Err(self.runtime_error(Code::TypeError, "Only instances have methods."));
+        };
+
+        // Fields shadow methods, so a stored function is called like any other value
+        let field = instance.fields.borrow().get(name);
+        if let Some(field) = field {
+            self.set_at(arg_count, field);
+            return self.call_value(field, arg_count);
+        }
+        self.invoke_from_class(instance.class, name, arg_count)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        arg_count: usize,
+    ) -> Result<(), Error> {
+        let class = class.as_class().expect("Instances are created from classes");
+        let method = class.methods.borrow().get(name);
+        match method {
+            Some(LoxValue::Obj(method)) => self.call(method, arg_count),
+            _ => Err(self.runtime_error(
+                Code::UndefinedProperty,
+                &format!("Undefined property '{name}'."),
+            )),
+        }
+    }
+
+    // Replaces the value on top of the stack with the method bound to it
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> Result<(), Error> {
+        let class = class.as_class().expect("Instances are created from classes");
+        let method = class.methods.borrow().get(name);
+        let Some(LoxValue::Obj(method)) = method else {
+            return Err(self.runtime_error(
+                Code::UndefinedProperty,
+                &format!("Undefined property '{name}'."),
+            ));
+        };
+
+        // The receiver stays on the stack until the bound method is allocated
+        let receiver = self.peek();
+        let bound = self.alloc(ObjKind::BoundMethod(ObjBoundMethod { receiver, method }));
+        self.pop();
+        self.push(&LoxValue::Obj(bound));
+        Ok(())
+    }
+
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> Result<(), Error> {
+        let function = closure
+            .as_closure()
+            .expect("Only closures are called")
+            .function();
+        let arity = function.arity;
+        if arg_count != arity as usize {
+            return Err(self.runtime_error(
+                Code::ArityMismatch,
+                &format!("Expected {arity} arguments but got {arg_count}."),
+            ));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error(Code::StackOverflow, "Stack overflow."));
+        }
+
+        let ip = function
+            .bytecode
+            .get_base_ip()
+            .expect("Functions are only called after compilation finished");
+        let mut slots = self.sp.as_ref().unwrap().clone();
+        slots.dec(arg_count + 1);
+        self.frames.push(CallFrame {
+            closure,
+            ip,
+            slots,
+        });
+        Ok(())
+    }
+
+    // Every allocation of a running program may collect garbage, so values still in use have
+    // to be reachable from the roots, e.g. by leaving them on the stack
+    fn alloc(&mut self, kind: ObjKind) -> ObjRef {
+        self.collect_garbage();
+        self.heap.alloc(kind)
+    }
+
+    fn intern(&mut self, value: &str) -> ObjRef {
+        self.collect_garbage();
+        self.heap.intern(value)
+    }
+
+    fn collect_garbage(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+
+        if let Some(sp) = &self.sp {
+            for value in self.stack.get_stack_iterator(sp.clone()) {
+                self.heap.mark_value(value);
             }
         }
+        for frame in &self.frames {
+            self.heap.mark_object(frame.closure);
+        }
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_object(*upvalue);
+        }
+        self.heap.mark_table(&self.globals);
+        self.heap.mark_object(self.init_string);
+        self.heap.collect();
+    }
+
+    // Reports the error at the current instruction, with a trace of the calls leading up to it
+    fn runtime_error(&mut self, code: Code, message: &str) -> Error {
+        let mut error = Diagnostic::error(code, message, Span::default());
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            let bytecode = frame.bytecode();
+            // The instruction pointer was already advanced past the failing instruction
+            let offset = bytecode.get_offset(&frame.ip) - 1;
+            let location = bytecode.location_for_offset(offset);
+            if depth == 0 {
+                error.span = location.into();
+            }
+            let line = location.line;
+            error = match frame.closure().function().name {
+                Some(name) => error.with_note(format!("[line {line}] in {name}()")),
+                None => error.with_note(format!("[line {line}] in script")),
+            };
+        }
+        self.runtime_error = Some(error);
+        self.unwind();
+        Error::Runtime
+    }
+
+    // Abandons every running call
+    fn unwind(&mut self) {
+        // Closures may have escaped the unwound frames, they keep the values of their variables
+        let base = self.stack.get_base_sp();
+        self.close_upvalues(&base);
+        self.frames.clear();
+    }
+
+    fn trace_instruction(&mut self, inst: &Op) -> io::Result<()> {
+        writeln!(self.diagnostics, "          ")?;
+        for slot in self
+            .stack
+            .get_stack_iterator(self.sp.as_mut().unwrap().clone())
+        {
+            write!(self.diagnostics, "[ {slot} ]")?;
+        }
+        writeln!(self.diagnostics)?;
+        writeln!(self.diagnostics, "{inst}")
     }
 
     fn read_u8(&mut self) -> u8 {
-        let byte = self.ip.as_ref().unwrap().get_u8();
-        self.ip.as_mut().unwrap().inc(1);
+        let ip = &mut self.frame_mut().ip;
+        let byte = ip.get_u8();
+        ip.inc(1);
         byte
     }
 
+    fn read_u16(&mut self) -> u16 {
+        let ip = &mut self.frame_mut().ip;
+        let short = ip.get_u16();
+        ip.inc(2);
+        short
+    }
+
     fn read_constant(&mut self) -> LoxValue {
         let index = self.read_u8() as usize;
-        self.bytecode.as_ref().unwrap().get_constant(index).clone()
+        self.frame().bytecode().get_constant(index)
+    }
+
+    fn read_identifier(&mut self) -> ObjRef {
+        match self.read_constant() {
+            LoxValue::Obj(name) => name,
+            other => panic!("Expected an identifier constant, found {other}"),
+        }
+    }
+
+    fn read_upvalue(&mut self) -> ObjRef {
+        let index = self.read_u8() as usize;
+        self.frame().closure().upvalues[index]
+    }
+
+    fn peek(&self) -> LoxValue {
+        self.peek_at(0)
+    }
+
+    // Reads the value `distance` slots below the top of the stack
+    fn peek_at(&self, distance: usize) -> LoxValue {
+        let mut sp = self.sp.as_ref().unwrap().clone();
+        sp.dec(distance + 1);
+        sp.get_value()
+    }
+
+    // Overwrites the value `distance` slots below the top of the stack
+    fn set_at(&mut self, distance: usize, value: LoxValue) {
+        let mut sp = self.sp.as_ref().unwrap().clone();
+        sp.dec(distance + 1);
+        sp.write_value(&value);
     }
 
     fn push(&mut self, value: &LoxValue) {
@@ -94,57 +573,287 @@ impl VM {
         self.push(&constant);
     }
 
-    fn op_add(&mut self) {
+    fn op_print(&mut self) -> Result<(), Error> {
+        let val = self.pop();
+        writeln!(self.out, "{val}")?;
+        Ok(())
+    }
+
+    fn op_get_local(&mut self) {
+        let slot = self.read_u8() as usize;
+        let mut local = self.frame().slots.clone();
+        local.inc(slot);
+        self.push(&local.get_value());
+    }
+
+    fn op_set_local(&mut self) {
+        let slot = self.read_u8() as usize;
+        // Assignment is an expression, so the value stays on the stack
+        let value = self.peek();
+        let mut local = self.frame().slots.clone();
+        local.inc(slot);
+        local.write_value(&value);
+    }
+
+    fn op_closure(&mut self) {
+        let function = self.read_constant();
+        let upvalue_count = function
+            .as_function()
+            .expect("Closures are created from function constants")
+            .upvalue_count;
+
+        let mut upvalues = Vec::with_capacity(upvalue_count);
+        for _ in 0..upvalue_count {
+            let is_local = self.read_u8() == 1;
+            let index = self.read_u8() as usize;
+            let upvalue = if is_local {
+                let mut slot = self.frame().slots.clone();
+                slot.inc(index);
+                self.capture_upvalue(slot)
+            } else {
+                self.frame().closure().upvalues[index]
+            };
+            upvalues.push(upvalue);
+        }
+
+        let LoxValue::Obj(function) = function else {
+            unreachable!("Checked above")
+        };
+        // The captured upvalues are open or belong to the current closure, so they are rooted
+        let closure = self.alloc(ObjKind::Closure(ObjClosure { function, upvalues }));
+        self.push(&LoxValue::Obj(closure));
+    }
+
+    // Closures capturing the same variable share a single upvalue
+    fn capture_upvalue(&mut self, slot: Sp<STACK_SIZE>) -> ObjRef {
+        let slot_of = |upvalue: &ObjRef| upvalue.as_upvalue().and_then(ObjUpvalue::slot);
+
+        let position = self
+            .open_upvalues
+            .iter()
+            .rposition(|upvalue| slot_of(upvalue).is_some_and(|open| open <= slot));
+        if let Some(position) = position {
+            let upvalue = self.open_upvalues[position];
+            if slot_of(&upvalue) == Some(slot.clone()) {
+                return upvalue;
+            }
+        }
+
+        let upvalue = self.alloc(ObjKind::Upvalue(ObjUpvalue::new(slot)));
+        let index = position.map_or(0, |position| position + 1);
+        self.open_upvalues.insert(index, upvalue);
+        upvalue
+    }
+
+    // Closes every open upvalue at or above `last`
+    fn close_upvalues(&mut self, last: &Sp<STACK_SIZE>) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let upvalue = upvalue.as_upvalue().expect("Only upvalues are open");
+            if upvalue.slot().is_none_or(|slot| slot < *last) {
+                break;
+            }
+            upvalue.close();
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn op_method(&mut self) {
+        let name = self.read_identifier();
+        let method = self.peek();
+        let class = self.peek_at(1);
+        if let LoxValue::Obj(class) = class {
+            let class = class.as_class().expect("Methods are defined on a class");
+            class.methods.borrow_mut().insert(name, method);
+        }
+        self.pop();
+    }
+
+    // Copies the methods of the superclass down into the subclass, methods declared in the
+    // subclass are added afterwards and override them
+    fn op_inherit(&mut self) -> Result<(), Error> {
+        let superclass = self.peek_at(1);
+        let Some(superclass) = superclass.as_class() else {
+            return Err(self.runtime_error(Code::TypeError, "Superclass must be a class."));
+        };
+        let LoxValue::Obj(subclass) = self.peek() else {
+            unreachable!("Inherit is only emitted for classes")
+        };
+        let subclass = subclass.as_class().expect("Inherit is only emitted for classes");
+
+        subclass
+            .methods
+            .borrow_mut()
+            .extend_from(&superclass.methods.borrow());
+        self.pop();
+        Ok(())
+    }
+
+    fn op_get_property(&mut self) -> Result<(), Error> {
+        let receiver = self.peek();
+        let Some(instance) = receiver.as_instance() else {
+            return Err(self.runtime_error(Code::TypeError, "Only instances have properties."));
+        };
+        let name = self.read_identifier();
+
+        let field = instance.fields.borrow().get(name);
+        match field {
+            Some(value) => {
+                self.pop();
+                self.push(&value);
+                Ok(())
+            }
+            None => self.bind_method(instance.class, name),
+        }
+    }
+
+    fn op_set_property(&mut self) -> Result<(), Error> {
+        let receiver = self.peek_at(1);
+        let Some(instance) = receiver.as_instance() else {
+            return Err(self.runtime_error(Code::TypeError, "Only instances have fields."));
+        };
+        let name = self.read_identifier();
+
+        let value = self.pop();
+        instance.fields.borrow_mut().insert(name, value);
+        // Assignment is an expression, so the value replaces the instance on the stack
+        self.pop();
+        self.push(&value);
+        Ok(())
+    }
+
+    fn op_define_global(&mut self) {
+        let name = self.read_identifier();
+        let value = self.pop();
+        self.globals.insert(name, value);
+    }
+
+    fn op_get_global(&mut self) -> Result<(), Error> {
+        let name = self.read_identifier();
+        match self.globals.get(name) {
+            Some(value) => {
+                self.push(&value);
+                Ok(())
+            }
+            None => Err(self.runtime_error(
+                Code::UndefinedVariable,
+                &format!("Undefined variable '{name}'."),
+            )),
+        }
+    }
+
+    fn op_set_global(&mut self) -> Result<(), Error> {
+        let name = self.read_identifier();
+        // Assignment is an expression, so the value stays on the stack
+        let value = self.peek();
+        match self.globals.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(self.runtime_error(
+                Code::UndefinedVariable,
+                &format!("Undefined variable '{name}'."),
+            )),
+        }
+    }
+
+    fn op_add(&mut self) -> Result<(), Error> {
         let b = self.pop();
         let a = self.pop();
         match (a, b) {
             (LoxValue::Number(a), LoxValue::Number(b)) => self.push(&LoxValue::Number(a + b)),
-            _ => panic!("Operands must be numbers"),
+            _ => match (a.as_string(), b.as_string()) {
+                (Some(a), Some(b)) => {
+                    let concatenated = [&*a.value, &*b.value].concat();
+                    let string = self.intern(&concatenated);
+                    self.push(&LoxValue::Obj(string));
+                }
+                _ => {
+                    return Err(self.runtime_error(
+                        Code::TypeError,
+                        "Operands must be two numbers or two strings.",
+                    ))
+                }
+            },
         }
+        Ok(())
     }
 
-    fn op_subtract(&mut self) {
+    fn op_subtract(&mut self) -> Result<(), Error> {
         let b = self.pop();
         let a = self.pop();
         match (a, b) {
             (LoxValue::Number(a), LoxValue::Number(b)) => self.push(&LoxValue::Number(a - b)),
-            _ => panic!("Operands must be numbers"),
+            _ => return Err(self.runtime_error(Code::TypeError, "Operands must be numbers.")),
         }
+        Ok(())
     }
 
-    fn op_multiply(&mut self) {
+    fn op_multiply(&mut self) -> Result<(), Error> {
         let b = self.pop();
         let a = self.pop();
         match (a, b) {
             (LoxValue::Number(a), LoxValue::Number(b)) => self.push(&LoxValue::Number(a * b)),
-            _ => panic!("Operands must be numbers"),
+            _ => return Err(self.runtime_error(Code::TypeError, "Operands must be numbers.")),
         }
+        Ok(())
     }
 
-    fn op_divide(&mut self) {
+    fn op_divide(&mut self) -> Result<(), Error> {
         let b = self.pop();
         let a = self.pop();
         match (a, b) {
             (LoxValue::Number(a), LoxValue::Number(b)) => self.push(&LoxValue::Number(a / b)),
-            _ => panic!("Operands must be numbers"),
+            _ => return Err(self.runtime_error(Code::TypeError, "Operands must be numbers.")),
+        }
+        Ok(())
+    }
+
+    fn op_greater(&mut self) -> Result<(), Error> {
+        let b = self.pop();
+        let a = self.pop();
+        match (a, b) {
+            (LoxValue::Number(a), LoxValue::Number(b)) => self.push(&LoxValue::Bool(a > b)),
+            _ => return Err(self.runtime_error(Code::TypeError, "Operands must be numbers.")),
         }
+        Ok(())
     }
 
-    fn op_negate(&mut self) {
+    fn op_less(&mut self) -> Result<(), Error> {
+        let b = self.pop();
+        let a = self.pop();
+        match (a, b) {
+            (LoxValue::Number(a), LoxValue::Number(b)) => self.push(&LoxValue::Bool(a < b)),
+            _ => return Err(self.runtime_error(Code::TypeError, "Operands must be numbers.")),
+        }
+        Ok(())
+    }
+
+    fn op_negate(&mut self) -> Result<(), Error> {
         let val = self.pop();
         match val {
             LoxValue::Number(num) => self.push(&LoxValue::Number(-num)),
+            _ => return Err(self.runtime_error(Code::TypeError, "Operand must be a number.")),
         }
+        Ok(())
     }
 }
 
 impl Default for VM {
     fn default() -> Self {
-        Self {
-            ip: None,
-            sp: None,
-            bytecode: None,
-            stack: Stack::new(),
-        }
+        Self::with_output(io::stdout(), io::stderr())
+    }
+}
+
+impl fmt::Debug for VM {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VM")
+            .field("frames", &self.frames)
+            .field("sp", &self.sp)
+            .field("stack", &self.stack)
+            .field("globals", &self.globals)
+            .field("heap", &self.heap)
+            .field("debug_options", &self.debug_options)
+            .finish_non_exhaustive()
     }
 }
@@ -0,0 +1,165 @@
+mod common;
+
+use std::{
+    fs, mem,
+    path::{Path, PathBuf},
+};
+
+use common::XorShift;
+use loxidize::{
+    bytecode::{Bytecode, Location},
+    compiler::Compiler,
+    heap::Heap,
+    opcodes::Op,
+};
+
+// Writes `len` bytes with runs of random length, returns the location of every byte
+fn generate(bytecode: &mut Bytecode, len: usize, seed: u64) -> Vec<Location> {
+    let mut rng = XorShift(seed);
+    let mut location = Location {
+        line: 1,
+        column: 1,
+        ..Location::default()
+    };
+    let mut locations = Vec::with_capacity(len);
+
+    while locations.len() < len {
+        let run = 1 + rng.below(8);
+        for _ in 0..run.min(len - locations.len()) {
+            bytecode.write_u8(Op::Nil.into(), location);
+            locations.push(location);
+        }
+        // Either a later column on the same line or one of the next lines
+        location = if rng.below(2) == 0 {
+            Location {
+                column: location.column + 1 + rng.below(10) as u32,
+                ..location
+            }
+        } else {
+            Location {
+                line: location.line + 1 + rng.below(3) as u32,
+                column: 1 + rng.below(40) as u32,
+                ..location
+            }
+        };
+        // Spans of enclosing expressions start before the ones of earlier instructions
+        location.start = rng.below(100_000) as u32;
+        location.end = location.start + rng.below(200) as u32;
+    }
+    locations
+}
+
+#[test]
+fn lookup_matches_every_offset_of_a_large_chunk() {
+    for seed in 1..=4u64 {
+        let mut bytecode = Bytecode::new();
+        let locations = generate(&mut bytecode, 200_000, seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+        assert_eq!(bytecode.get_code_len(), locations.len());
+        for (offset, location) in locations.iter().enumerate() {
+            assert_eq!(bytecode.location_for_offset(offset), *location);
+            assert_eq!(bytecode.line_for_offset(offset), location.line);
+        }
+    }
+}
+
+// Every Lox file below the directory
+fn lox_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(lox_files(&path));
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+    files
+}
+
+// Bytes of code and of its locations, including the functions declared in it
+fn sizes(bytecode: &Bytecode) -> (usize, usize) {
+    let mut code = bytecode.get_code_len();
+    let mut locations = bytecode.location_bytes();
+    for function in bytecode.constants().iter().filter_map(|c| c.as_function()) {
+        let (function_code, function_locations) = sizes(&function.bytecode);
+        code += function_code;
+        locations += function_locations;
+    }
+    (code, locations)
+}
+
+#[test]
+fn locations_of_real_code_take_less_memory_than_a_line_per_byte() {
+    let (mut code, mut locations) = (0, 0);
+    for path in lox_files(Path::new("res")) {
+        let source = fs::read_to_string(&path).unwrap();
+        let mut heap = Heap::new();
+        if let Ok(bytecode) = Compiler::default().compile(&source, &mut heap, &mut String::new()) {
+            let (file_code, file_locations) = sizes(&bytecode);
+            code += file_code;
+            locations += file_locations;
+        }
+    }
+    assert!(locations < code * mem::size_of::<i32>());
+}
+
+#[test]
+fn disassembly_marks_instructions_on_the_same_line() {
+    let mut bytecode = Bytecode::new();
+    for (line, column) in [(1, 1), (1, 5), (2, 1), (4, 3), (4, 3)] {
+        let location = Location {
+            line,
+            column,
+            ..Location::default()
+        };
+        bytecode.write_u8(Op::Nil.into(), location);
+    }
+
+    let disassembly = bytecode.disassemble("test");
+    let lines: Vec<&str> = disassembly.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "== test ==",
+            "0000    1 OP_NIL",
+            "0001    | OP_NIL",
+            "0002    2 OP_NIL",
+            "0003    4 OP_NIL",
+            "0004    | OP_NIL",
+        ]
+    );
+}
+
+#[test]
+#[should_panic(expected = "outside of the code")]
+fn lookup_past_the_end_panics() {
+    let mut bytecode = Bytecode::new();
+    bytecode.write_u8(Op::Nil.into(), Location::default());
+
+    bytecode.location_for_offset(1);
+}
+
+#[test]
+fn disassembly_lines_up_the_operands() {
+    let mut bytecode = Bytecode::new();
+    let location = Location {
+        line: 1,
+        column: 1,
+        ..Location::default()
+    };
+    for byte in [Op::GetLocal.into(), 3, Op::SetUpvalue.into(), 12] {
+        bytecode.write_u8(byte, location);
+    }
+
+    let disassembly = bytecode.disassemble("test");
+    let lines: Vec<&str> = disassembly.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "== test ==",
+            "0000    1 OP_GET_LOCAL     0003",
+            "0002    | OP_SET_UPVALUE   0012",
+        ]
+    );
+}
@@ -0,0 +1,203 @@
+use loxidize::{
+    compiler::{Compiler, DebugOptions},
+    diagnostics::{Code, Renderer},
+    heap::Heap,
+    vm::Error,
+};
+
+// Returns the errors rendered like the VM does by default
+fn compile_errors(source: &str) -> (Result<(), Error>, String) {
+    let mut heap = Heap::new();
+    let result = Compiler::new(DebugOptions::default()).compile(source, &mut heap, &mut String::new());
+    match result {
+        Ok(_) => (Ok(()), String::new()),
+        Err(errors) => {
+            let rendered = errors
+                .iter()
+                .map(|error| Renderer::default().render(error, source))
+                .collect();
+            (Err(Error::Compile), rendered)
+        }
+    }
+}
+
+#[test]
+fn return_at_top_level_is_a_compile_error() {
+    let (result, diagnostics) = compile_errors("return \"wat\";");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert!(
+        diagnostics.contains("Error at 'return': Can't return from top-level code."),
+        "{diagnostics}"
+    );
+}
+
+#[test]
+fn return_inside_function_compiles() {
+    let (result, diagnostics) = compile_errors("fun f() { return 1; }");
+
+    assert!(result.is_ok(), "{diagnostics}");
+}
+
+#[test]
+fn this_outside_of_a_class_is_a_compile_error() {
+    let (result, diagnostics) = compile_errors("fun f() { print this; }");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert!(
+        diagnostics.contains("Error at 'this': Can't use 'this' outside of a class."),
+        "{diagnostics}"
+    );
+}
+
+#[test]
+fn returning_a_value_from_an_initializer_is_a_compile_error() {
+    let (result, diagnostics) = compile_errors("class Foo { init() { return 1; } }");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert!(
+        diagnostics.contains("Error at 'return': Can't return a value from an initializer."),
+        "{diagnostics}"
+    );
+}
+
+#[test]
+fn inheriting_from_itself_is_a_compile_error() {
+    let (result, diagnostics) = compile_errors("class Foo < Foo {}");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert!(
+        diagnostics.contains("Error at 'Foo': A class can't inherit from itself."),
+        "{diagnostics}"
+    );
+}
+
+#[test]
+fn super_outside_of_a_class_is_a_compile_error() {
+    let (result, diagnostics) = compile_errors("fun f() { super.foo(); }");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert!(
+        diagnostics.contains("Error at 'super': Can't use 'super' outside of a class."),
+        "{diagnostics}"
+    );
+}
+
+#[test]
+fn super_without_a_superclass_is_a_compile_error() {
+    let (result, diagnostics) = compile_errors("class Foo { bar() { return super.bar; } }");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert!(
+        diagnostics.contains("Error at 'super': Can't use 'super' in a class with no superclass."),
+        "{diagnostics}"
+    );
+}
+
+#[test]
+fn lexing_errors_are_reported_and_skipped() {
+    let (result, diagnostics) = compile_errors("print 1 #;\nprint 2; \"unterminated");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert!(diagnostics.contains("Error: Unexpected character."));
+    assert!(diagnostics.contains("Error: Unterminated string."));
+}
+
+#[test]
+fn instructions_are_located_at_their_source() {
+    let source = "print 1;\n  print -2;";
+    let mut heap = Heap::new();
+    let bytecode = Compiler::new(DebugOptions::default())
+        .compile(source, &mut heap, &mut String::new())
+        .unwrap();
+
+    let locations: Vec<(u32, u32, &str)> = (0..bytecode.get_code_len())
+        .map(|offset| bytecode.location_for_offset(offset))
+        .map(|location| {
+            let span = location.start as usize..location.end as usize;
+            (location.line, location.column, &source[span])
+        })
+        .collect();
+    assert_eq!(
+        locations,
+        [
+            // Constant and print of the first statement
+            (1, 7, "1"),
+            (1, 7, "1"),
+            (1, 1, "print 1;"),
+            // Constant, negation and print of the second one
+            (2, 10, "2"),
+            (2, 10, "2"),
+            (2, 9, "-2"),
+            (2, 3, "print -2;"),
+            // Implicit return at the end of the input
+            (2, 12, ""),
+            (2, 12, ""),
+        ]
+    );
+}
+
+#[test]
+fn every_declaration_with_an_error_is_reported() {
+    let source = "var = 1;\nprint (1;\nfun f() { return this; }\n";
+    let errors = Compiler::new(DebugOptions::default())
+        .compile(source, &mut Heap::new(), &mut String::new())
+        .unwrap_err();
+
+    let errors: Vec<(Code, usize)> = errors
+        .iter()
+        .map(|error| (error.code, error.span.line))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            (Code::ExpectedToken, 1),
+            (Code::ExpectedToken, 2),
+            (Code::ThisOutsideClass, 3)
+        ]
+    );
+}
+
+#[test]
+fn parse_and_compile_errors_are_reported_in_source_order() {
+    let (result, diagnostics) = compile_errors("{ var a = 1; var a = 2; }\nprint (;");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert_eq!(
+        diagnostics,
+        "\
+[line 1] Error at 'a': Already a variable with this name in this scope.
+[line 2] Error at ';': Expect expression.
+"
+    );
+}
+
+#[test]
+fn errors_following_from_an_error_are_suppressed() {
+    // The closing parenthesis and semicolon are missing as well once `=` ends the expression
+    let (result, diagnostics) = compile_errors("print (1 = 2);\nprint 3;");
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert_eq!(
+        diagnostics,
+        "[line 1] Error at '=': Invalid assignment target.\n"
+    );
+}
+
+#[test]
+fn exceeded_limits_are_reported_once_per_function() {
+    let constants: String = (0..300).map(|i| format!("print {i};")).collect();
+    let locals: String = (0..300).map(|i| format!("var a{i};")).collect();
+    let source = format!("{constants}\nfun f() {{ {constants} }}\nfun g() {{ {locals} }}");
+    let (result, diagnostics) = compile_errors(&source);
+
+    assert!(matches!(result, Err(Error::Compile)));
+    assert_eq!(
+        diagnostics,
+        "\
+[line 1] Error at '256': Too many constants in one chunk.
+[line 2] Error at '256': Too many constants in one chunk.
+[line 3] Error at 'a255': Too many local variables in function.
+"
+    );
+}
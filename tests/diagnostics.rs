@@ -0,0 +1,173 @@
+mod common;
+
+use common::Capture;
+use loxidize::{
+    compiler::{Compiler, DebugOptions},
+    diagnostics::{Code, Diagnostic, Phase, Renderer, Style},
+    heap::Heap,
+    token::Span,
+    vm::VM,
+};
+
+const RICH: Renderer = Renderer::new(Style::Rich { color: false });
+
+fn compile_errors(source: &str, renderer: Renderer) -> String {
+    let result =
+        Compiler::new(DebugOptions::default()).compile(source, &mut Heap::new(), &mut String::new());
+    let Err(errors) = result else {
+        panic!("Expected {source:?} to have compile errors");
+    };
+    errors
+        .iter()
+        .map(|error| renderer.render(error, source))
+        .collect()
+}
+
+fn runtime_error(source: &str, renderer: Renderer) -> String {
+    let diagnostics = Capture::default();
+    let mut vm = VM::with_output(Capture::default(), diagnostics.clone());
+    vm.set_renderer(renderer);
+    assert!(vm.interpret(source).is_err());
+    diagnostics.contents()
+}
+
+#[test]
+fn codes_are_numbered_by_phase() {
+    assert_eq!(Code::UnexpectedCharacter.to_string(), "E0101");
+    assert_eq!(Code::ExpectedToken.to_string(), "E0201");
+    assert_eq!(Code::ReturnAtTopLevel.to_string(), "E0301");
+    assert_eq!(Code::TypeError.to_string(), "E0401");
+    assert_eq!(Code::InvalidNumber.phase(), Phase::Lexing);
+    assert_eq!(Code::TooMuchCode.phase(), Phase::Compiling);
+}
+
+#[test]
+fn plain_errors_match_clox() {
+    let diagnostics = compile_errors("var a = 1;\nvar a = 2\n", Renderer::default());
+
+    assert_eq!(
+        diagnostics,
+        "[line 3] Error at end: Expect ';' after variable declaration.\n"
+    );
+}
+
+#[test]
+fn plain_lexing_errors_are_reported_where_scanning_stopped() {
+    let diagnostics = compile_errors("print 1;\n\"unterminated\nstring", Renderer::default());
+
+    assert_eq!(diagnostics, "[line 3] Error: Unterminated string.\n");
+}
+
+#[test]
+fn plain_runtime_errors_are_followed_by_the_trace() {
+    let diagnostics = runtime_error("fun f() {\n  return -nil;\n}\nf();", Renderer::default());
+
+    assert_eq!(
+        diagnostics,
+        "Operand must be a number.\n[line 2] in f()\n[line 4] in script\n"
+    );
+}
+
+#[test]
+fn rich_errors_underline_the_lexeme() {
+    let diagnostics = compile_errors("fun f() {\n  var a = 1;\n  var a = 2;\n}", RICH);
+
+    assert_eq!(
+        diagnostics,
+        "\
+error[E0308]: Already a variable with this name in this scope.
+ --> 3:7
+  |
+3 |   var a = 2;
+  |       ^
+"
+    );
+}
+
+#[test]
+fn rich_errors_show_labels() {
+    let diagnostics = compile_errors("class Foo < Foo {}", RICH);
+
+    assert_eq!(
+        diagnostics,
+        "\
+error[E0306]: A class can't inherit from itself.
+ --> 1:13
+  |
+1 | class Foo < Foo {}
+  |       --- the class being declared
+  |             ^^^
+"
+    );
+}
+
+#[test]
+fn misplaced_returns_underline_the_keyword() {
+    let diagnostics = compile_errors("class A {\n  init() { return 1; }\n}", RICH);
+
+    assert_eq!(
+        diagnostics,
+        "\
+error[E0302]: Can't return a value from an initializer.
+ --> 2:12
+  |
+2 |   init() { return 1; }
+  |            ^^^^^^
+"
+    );
+}
+
+#[test]
+fn rich_runtime_errors_point_at_the_failing_expression() {
+    let source = "var a = 1;\nprint a + \"b\";";
+    let diagnostics = runtime_error(source, RICH);
+
+    assert_eq!(
+        diagnostics,
+        "\
+error[E0401]: Operands must be two numbers or two strings.
+ --> 2:7
+  |
+2 | print a + \"b\";
+  |       ^^^^^^^
+  = note: [line 2] in script
+"
+    );
+}
+
+#[test]
+fn gutter_fits_the_widest_line_number() {
+    let source = format!("{}print x", "\n".repeat(99));
+    let span = Span {
+        start: 105,
+        end: 106,
+        line: 100,
+        column: 7,
+    };
+    let diagnostic = Diagnostic::error(Code::UndefinedVariable, "Undefined variable 'x'.", span)
+        .with_note("[line 100] in script");
+
+    assert_eq!(
+        RICH.render(&diagnostic, &source),
+        "\
+error[E0402]: Undefined variable 'x'.
+   --> 100:7
+    |
+100 | print x
+    |       ^
+    = note: [line 100] in script
+"
+    );
+}
+
+#[test]
+fn color_is_optional() {
+    let colored = Renderer::new(Style::Rich { color: true });
+    let diagnostics = compile_errors("print 1", colored);
+
+    assert!(
+        diagnostics.contains("\x1b[1;31merror[E0201]"),
+        "{diagnostics}"
+    );
+    assert!(!compile_errors("print 1", RICH).contains('\x1b'));
+}
@@ -0,0 +1,114 @@
+use loxidize::{
+    heap::Heap,
+    lox_value::LoxValue,
+    object::{ObjClass, ObjInstance, ObjKind},
+    vm::{Error, VM},
+};
+
+#[test]
+fn unreachable_objects_are_freed() {
+    let mut heap = Heap::new();
+    let kept = heap.intern("kept");
+    heap.intern("garbage");
+    let before = heap.bytes_allocated();
+
+    heap.mark_object(kept);
+    heap.collect();
+
+    assert_eq!(heap.object_count(), 1);
+    assert!(heap.bytes_allocated() < before);
+    assert_eq!(&*kept.as_string().unwrap().value, "kept");
+}
+
+#[test]
+fn references_of_marked_objects_survive() {
+    let mut heap = Heap::new();
+    let name = heap.intern("Point");
+    let class = heap.alloc(ObjKind::Class(ObjClass::new(name)));
+    let instance = heap.alloc(ObjKind::Instance(ObjInstance::new(class)));
+    let field = heap.intern("x");
+    let value = heap.intern("value");
+    instance
+        .as_instance()
+        .unwrap()
+        .fields
+        .borrow_mut()
+        .insert(field, LoxValue::Obj(value));
+    heap.intern("garbage");
+
+    heap.mark_object(instance);
+    heap.collect();
+
+    // The instance, its class, the class name, the field name and the field value
+    assert_eq!(heap.object_count(), 5);
+}
+
+#[test]
+fn cycles_are_freed() {
+    let mut heap = Heap::new();
+    let name = heap.intern("Node");
+    let class = heap.alloc(ObjKind::Class(ObjClass::new(name)));
+    let instance = heap.alloc(ObjKind::Instance(ObjInstance::new(class)));
+    let field = heap.intern("next");
+    instance
+        .as_instance()
+        .unwrap()
+        .fields
+        .borrow_mut()
+        .insert(field, LoxValue::Obj(instance));
+
+    heap.collect();
+
+    assert_eq!(heap.object_count(), 0);
+    assert_eq!(heap.bytes_allocated(), 0);
+}
+
+#[test]
+fn intern_table_does_not_keep_strings_alive() {
+    let mut heap = Heap::new();
+    heap.intern("lox");
+
+    heap.collect();
+    assert_eq!(heap.object_count(), 0);
+
+    // The swept string must not be found in the intern table anymore
+    let string = heap.intern("lox");
+    assert_eq!(heap.object_count(), 1);
+    assert_eq!(&*string.as_string().unwrap().value, "lox");
+}
+
+#[test]
+fn marks_are_cleared_after_a_collection() {
+    let mut heap = Heap::new();
+    let string = heap.intern("lox");
+
+    heap.mark_object(string);
+    heap.collect();
+    assert_eq!(heap.object_count(), 1);
+
+    heap.collect();
+    assert_eq!(heap.object_count(), 0);
+}
+
+#[test]
+fn closures_escaping_a_runtime_error_keep_their_variables() {
+    let mut vm = VM::with_output(vec![], vec![]);
+    let escape = "
+        var f;
+        {
+            var captured = \"captured\";
+            fun g() { print captured + \"!\"; }
+            f = g;
+            nil + 1;
+        }";
+    assert!(matches!(vm.interpret(escape), Err(Error::Runtime)));
+
+    // Reuses the stack slot of the captured variable and collects garbage along the way
+    let pressure = "
+        for (var i = 0; i < 10000; i = i + 1) {
+            var garbage = \"a\" + \"b\";
+        }";
+    assert!(vm.interpret(pressure).is_ok());
+
+    assert!(vm.interpret("f();").is_ok());
+}
@@ -0,0 +1,23 @@
+use loxidize::heap::Heap;
+
+#[test]
+fn interning_deduplicates_strings() {
+    let mut heap = Heap::new();
+
+    let a = heap.intern("lox");
+    let b = heap.intern("lox");
+    let c = heap.intern("clox");
+
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+    assert_eq!(heap.object_count(), 2);
+}
+
+#[test]
+fn interned_strings_keep_their_contents() {
+    let mut heap = Heap::new();
+
+    let string = heap.intern("A~¶Þॐஃ");
+
+    assert_eq!(&*string.as_string().unwrap().value, "A~¶Þॐஃ");
+}
@@ -0,0 +1,152 @@
+use std::iter;
+
+use logos::Logos;
+use loxidize::token::{next_token, LexingError, Span, Token, TokenSpan};
+
+fn lex(source: &str) -> Vec<Result<Token, LexingError>> {
+    let mut lexer = Token::lexer(source);
+    iter::from_fn(|| next_token(&mut lexer)).collect()
+}
+
+#[test]
+fn keywords_are_not_identifiers() {
+    let tokens = lex("and class else false for fun if nil or print return super this true var while");
+
+    assert_eq!(
+        tokens,
+        [
+            Token::And,
+            Token::Class,
+            Token::Else,
+            Token::False,
+            Token::For,
+            Token::Fun,
+            Token::If,
+            Token::Nil,
+            Token::Or,
+            Token::Print,
+            Token::Return,
+            Token::Super,
+            Token::This,
+            Token::True,
+            Token::Var,
+            Token::While,
+        ]
+        .map(Ok)
+    );
+}
+
+#[test]
+fn identifiers_may_start_with_keywords() {
+    let source = "truth thisOne";
+    let tokens = lex(source);
+
+    let spans: Vec<&str> = tokens
+        .iter()
+        .map(|token| match token {
+            Ok(Token::Identifier(span)) => span.string(source),
+            other => panic!("Expected an identifier, got {other:?}"),
+        })
+        .collect();
+    assert_eq!(spans, ["truth", "thisOne"]);
+}
+
+#[test]
+fn comments_and_whitespace_are_skipped() {
+    let tokens = lex("// a comment\r\n\t1 // another one\r\n+ 2\r\n");
+
+    assert_eq!(
+        tokens,
+        [Token::Number(1.0), Token::Plus, Token::Number(2.0)].map(Ok)
+    );
+}
+
+// Lines and columns of every token
+fn positions(source: &str) -> Vec<(usize, usize)> {
+    let mut lexer = Token::lexer(source);
+    let mut positions = vec![];
+    while lexer.next().is_some() {
+        let span = Span::of_token(&lexer);
+        positions.push((span.line, span.column));
+    }
+    positions
+}
+
+#[test]
+fn lines_and_columns_start_at_one() {
+    assert_eq!(
+        positions("var a = 1;\n  print a;"),
+        [(1, 1), (1, 5), (1, 7), (1, 9), (1, 10), (2, 3), (2, 9), (2, 10)]
+    );
+}
+
+#[test]
+fn carriage_returns_keep_line_numbers() {
+    assert_eq!(positions("a\r\nb\r\n\r\n c"), [(1, 1), (2, 1), (4, 2)]);
+}
+
+#[test]
+fn columns_count_characters() {
+    assert_eq!(positions("\"äöü\" x"), [(1, 1), (1, 7)]);
+}
+
+#[test]
+fn multiline_strings_advance_the_line() {
+    let source = "\"a\nb\nc\" d";
+    let mut lexer = Token::lexer(source);
+
+    assert_eq!(
+        lexer.next(),
+        Some(Ok(Token::String(TokenSpan { start: 0, end: 7 })))
+    );
+    // Strings are located at their opening quote
+    let span = Span::of_token(&lexer);
+    assert_eq!((span.line, span.column), (1, 1));
+    assert_eq!(lexer.extras.line, 3);
+
+    assert!(matches!(lexer.next(), Some(Ok(Token::Identifier(_)))));
+    let span = Span::of_token(&lexer);
+    assert_eq!((span.line, span.column), (3, 4));
+}
+
+#[test]
+fn unterminated_string_is_an_error() {
+    let tokens = lex("print \"unterminated\nstring");
+
+    assert_eq!(
+        tokens,
+        [
+            Ok(Token::Print),
+            Err(LexingError::UnterminatedString(TokenSpan { start: 6, end: 26 })),
+        ]
+    );
+}
+
+#[test]
+fn unexpected_character_is_reported_with_its_span() {
+    let tokens = lex("1 | ä");
+
+    assert_eq!(
+        tokens,
+        [
+            Ok(Token::Number(1.0)),
+            Err(LexingError::UnexpectedCharacter('|', TokenSpan { start: 2, end: 3 })),
+            Err(LexingError::UnexpectedCharacter('ä', TokenSpan { start: 4, end: 6 })),
+        ]
+    );
+}
+
+#[test]
+fn lexing_errors_use_clox_messages() {
+    let span = TokenSpan { start: 0, end: 1 };
+
+    assert_eq!(
+        LexingError::UnterminatedString(span).to_string(),
+        "Unterminated string."
+    );
+    assert_eq!(
+        LexingError::UnexpectedCharacter('#', span).to_string(),
+        "Unexpected character."
+    );
+    assert_eq!(LexingError::InvalidNumber(span).to_string(), "Invalid number.");
+}
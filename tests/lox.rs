@@ -1,22 +1,106 @@
+mod common;
+
 use std::path::PathBuf;
 
+use common::Capture;
+use loxidize::vm::{Error, VM};
 use rstest::rstest;
 
 const EXPECTED_COMMENT: &str = "// expect: ";
+const EXPECTED_RUNTIME_ERROR: &str = "// expect runtime error: ";
+// Compile errors are written either as `// Error at ...` which refers to the
+// line of the comment, or as `// [line N] Error at ...` for any other line
+const EXPECTED_ERROR: &str = "// Error";
+const EXPECTED_LINE_ERROR: &str = "// [line ";
+const EXPECTED_C_LINE_ERROR: &str = "// [c line ";
+
+#[derive(Debug, Default)]
+struct Expectations {
+    stdout: Vec<String>,
+    compile_errors: Vec<String>,
+    // The message and the line it is reported at
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Expectations {
+        let mut expectations = Expectations::default();
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+
+            if let Some(pos) = line.find(EXPECTED_COMMENT) {
+                let output = &line[pos + EXPECTED_COMMENT.len()..];
+                expectations.stdout.push(output.to_owned());
+            } else if let Some(pos) = line.find(EXPECTED_RUNTIME_ERROR) {
+                let message = &line[pos + EXPECTED_RUNTIME_ERROR.len()..];
+                expectations.runtime_error = Some((message.to_owned(), line_number));
+            } else if let Some(pos) = line.find(EXPECTED_ERROR) {
+                let error = &line[pos + "// ".len()..];
+                expectations
+                    .compile_errors
+                    .push(format!("[line {line_number}] {error}"));
+            } else if let Some(pos) = line
+                .find(EXPECTED_LINE_ERROR)
+                .or_else(|| line.find(EXPECTED_C_LINE_ERROR))
+            {
+                let annotation = &line[pos..];
+                let start = annotation.find("line ").unwrap();
+                let error = &annotation[start..];
+                expectations.compile_errors.push(format!("[{error}"));
+            }
+        }
+
+        expectations
+    }
+}
 
 #[rstest]
 fn lox_files(#[files("res/**/*.lox")] path: PathBuf) {
     let string_path = path.to_str().expect("Expected non empty path");
 
-    let lox_source =
-        std::fs::read_to_string(&path).expect(&format!("Expected to find a test {string_path}"));
+    let lox_source = std::fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("Expected to find a test {string_path}"));
+    let expected = Expectations::parse(&lox_source);
+
+    let out = Capture::default();
+    let diagnostics = Capture::default();
+    let mut vm = VM::with_output(out.clone(), diagnostics.clone());
+    let result = vm.interpret(&lox_source);
+
+    let stdout = out.contents();
+    let stderr = diagnostics.contents();
 
-    // Perhaps using matches would be cleaner
-    let expected_stdout: Vec<&str> = lox_source
-        .split('\n')
-        .filter(|line| line.contains(EXPECTED_COMMENT))
-        .map(|line| &line[line.find(EXPECTED_COMMENT).unwrap() + EXPECTED_COMMENT.len()..])
-        .collect();
+    let actual_stdout: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        actual_stdout, expected.stdout,
+        "Unexpected output of {string_path}\nstderr:\n{stderr}"
+    );
 
-    assert_eq!(expected_stdout.join("\n"), "Working");
+    if !expected.compile_errors.is_empty() {
+        let actual_errors: Vec<&str> = stderr
+            .lines()
+            .filter(|line| line.starts_with("[line "))
+            .collect();
+        assert_eq!(
+            actual_errors, expected.compile_errors,
+            "Unexpected compile errors in {string_path}"
+        );
+        assert!(matches!(result, Err(Error::Compile)), "stderr:\n{stderr}");
+    } else if let Some((message, line)) = &expected.runtime_error {
+        // The first line of the stack trace follows the message
+        let mut lines = stderr.lines().skip_while(|actual| actual != message);
+        assert!(
+            lines.next().is_some(),
+            "Expected runtime error \"{message}\" in {string_path}\nstderr:\n{stderr}"
+        );
+        let location = format!("[line {line}]");
+        assert!(
+            lines.next().is_some_and(|trace| trace.starts_with(&location)),
+            "Expected runtime error at {location} in {string_path}\nstderr:\n{stderr}"
+        );
+        assert!(matches!(result, Err(Error::Runtime)), "stderr:\n{stderr}");
+    } else {
+        assert!(result.is_ok(), "stderr:\n{stderr}");
+    }
 }
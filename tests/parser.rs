@@ -0,0 +1,102 @@
+mod common;
+
+use std::panic;
+
+use common::XorShift;
+use loxidize::{
+    compiler::{Compiler, DebugOptions},
+    diagnostics::{Code, Renderer, Style},
+    heap::Heap,
+    vm::{Error, VM},
+};
+
+// Every kind of token, along with input the lexer rejects
+const LEXEMES: &[&str] = &[
+    "(", ")", "{", "}", ",", ".", "-", "+", ";", "/", "*", "!", "!=", "=", "==", ">", ">=", "<",
+    "<=", "a", "b", "init", "\"str\"", "1", "2.5", "and", "class", "else", "false", "for", "fun",
+    "if", "nil", "or", "print", "return", "super", "this", "true", "var", "while", "#", "\"", "\n",
+];
+
+fn random_source(rng: &mut XorShift) -> String {
+    let len = rng.below(40);
+    (0..len)
+        .map(|_| LEXEMES[rng.below(LEXEMES.len())])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Compiles the source and renders its errors in every style
+fn compile(source: &str) -> bool {
+    let mut heap = Heap::new();
+    match Compiler::new(DebugOptions::default()).compile(source, &mut heap, &mut String::new()) {
+        Ok(_) => true,
+        Err(errors) => {
+            assert!(!errors.is_empty());
+            for error in &errors {
+                for style in [Style::Plain, Style::Rich { color: true }] {
+                    Renderer::new(style).render(error, source);
+                }
+            }
+            false
+        }
+    }
+}
+
+#[test]
+fn random_token_streams_never_panic() {
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..20_000 {
+        let source = random_source(&mut rng);
+        let result = panic::catch_unwind(|| compile(&source));
+        assert!(result.is_ok(), "Panicked while compiling {source:?}");
+    }
+}
+
+#[test]
+fn incomplete_expressions_are_compile_errors() {
+    for source in [
+        "1 +",
+        "print 1 +;",
+        "print (;",
+        "-",
+        "var a = ;",
+        "a.;",
+        "print 1 2;",
+    ] {
+        let mut vm = VM::with_output(vec![], vec![]);
+
+        assert!(
+            matches!(vm.interpret(source), Err(Error::Compile)),
+            "Expected {source:?} to be a compile error"
+        );
+    }
+}
+
+#[test]
+fn deep_nesting_is_reported_once_instead_of_overflowing_the_stack() {
+    for source in [
+        format!("print {}1;", "-".repeat(100_000)),
+        format!("print {}1;", "(".repeat(100_000)),
+        "{".repeat(100_000),
+        "if (true) ".repeat(10_000),
+        format!("var a = 1; print a{};", "+a".repeat(10_000)),
+        format!("var a; print a{};", ".b".repeat(10_000)),
+        format!("fun f() {{ return f; }} f{};", "()".repeat(10_000)),
+    ] {
+        let errors = Compiler::new(DebugOptions::default())
+            .compile(&source, &mut Heap::new(), &mut String::new())
+            .unwrap_err();
+
+        let codes: Vec<Code> = errors.iter().map(|error| error.code).collect();
+        assert_eq!(codes, [Code::NestingTooDeep]);
+    }
+}
+
+#[test]
+fn the_repl_keeps_running_after_a_syntax_error() {
+    let mut vm = VM::with_output(vec![], vec![]);
+
+    assert!(matches!(vm.interpret("var a = 1 +;"), Err(Error::Compile)));
+    assert!(vm.interpret("var a = 1;").is_ok());
+    assert!(vm.interpret("print a;").is_ok());
+}
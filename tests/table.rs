@@ -0,0 +1,129 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::XorShift;
+use loxidize::{
+    heap::Heap,
+    lox_value::LoxValue,
+    object::ObjRef,
+    table::{hash_str, Table},
+};
+
+fn keys(heap: &mut Heap, count: usize) -> Vec<ObjRef> {
+    (0..count).map(|i| heap.intern(&format!("key{i}"))).collect()
+}
+
+fn assert_matches(table: &Table, model: &HashMap<ObjRef, LoxValue>, keys: &[ObjRef]) {
+    assert_eq!(table.len(), model.len());
+    for key in keys {
+        assert_eq!(table.get(*key), model.get(key).copied());
+    }
+    assert_eq!(table.iter().count(), model.len());
+    for (key, value) in table.iter() {
+        assert_eq!(model.get(&key), Some(&value));
+    }
+}
+
+#[test]
+fn random_operations_match_hashmap() {
+    for seed in 1..=32u64 {
+        let mut heap = Heap::new();
+        // Few keys compared to operations, so keys get removed and reinserted a lot
+        let keys = keys(&mut heap, 64);
+        let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mut table = Table::new();
+        let mut model = HashMap::new();
+
+        for step in 0..2_000 {
+            let key = keys[rng.below(keys.len())];
+            let value = LoxValue::from(step);
+            match rng.below(3) {
+                0 | 1 => assert_eq!(
+                    table.insert(key, value),
+                    model.insert(key, value).is_none(),
+                    "seed {seed}, step {step}"
+                ),
+                _ => assert_eq!(
+                    table.remove(key),
+                    model.remove(&key),
+                    "seed {seed}, step {step}"
+                ),
+            }
+        }
+        assert_matches(&table, &model, &keys);
+    }
+}
+
+#[test]
+fn growing_keeps_every_entry() {
+    let mut heap = Heap::new();
+    let keys = keys(&mut heap, 10_000);
+    let mut table = Table::new();
+
+    for (i, key) in keys.iter().enumerate() {
+        assert!(table.insert(*key, LoxValue::from(i as f64)));
+        assert!(table.len() as f64 <= table.capacity() as f64 * 0.75);
+    }
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(table.get(*key), Some(LoxValue::from(i as f64)));
+    }
+}
+
+#[test]
+fn tombstones_keep_probe_sequences_intact() {
+    let mut heap = Heap::new();
+    let keys = keys(&mut heap, 4);
+    let mut table = Table::new();
+    for key in &keys {
+        table.insert(*key, LoxValue::Nil);
+    }
+
+    // Removing earlier keys must not hide later keys that probed past them
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(table.remove(*key), Some(LoxValue::Nil));
+        assert_eq!(table.remove(*key), None);
+        for later in &keys[i + 1..] {
+            assert!(table.contains_key(*later));
+        }
+    }
+    assert!(table.is_empty());
+
+    // Reinserting reuses tombstones instead of growing
+    let capacity = table.capacity();
+    for key in &keys {
+        assert!(table.insert(*key, LoxValue::Bool(true)));
+    }
+    assert_eq!(table.capacity(), capacity);
+}
+
+#[test]
+fn find_string_compares_contents() {
+    let mut heap = Heap::new();
+    let key = heap.intern("lox");
+    let mut table = Table::new();
+    table.insert(key, LoxValue::Nil);
+
+    assert_eq!(table.find_string("lox", hash_str("lox")), Some(key));
+    assert_eq!(table.find_string("clox", hash_str("clox")), None);
+
+    table.remove(key);
+    assert_eq!(table.find_string("lox", hash_str("lox")), None);
+}
+
+#[test]
+fn retain_removes_rejected_entries() {
+    let mut heap = Heap::new();
+    let keys = keys(&mut heap, 100);
+    let mut table = Table::new();
+    for (i, key) in keys.iter().enumerate() {
+        table.insert(*key, LoxValue::from(i as f64));
+    }
+
+    table.retain(|_, value| matches!(value, LoxValue::Number(n) if n % 2.0 == 0.0));
+
+    assert_eq!(table.len(), 50);
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(table.contains_key(*key), i % 2 == 0);
+    }
+}
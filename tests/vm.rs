@@ -0,0 +1,85 @@
+mod common;
+
+use std::io::{self, Write};
+
+use common::Capture;
+use loxidize::{
+    compiler::DebugOptions,
+    vm::{Error, VM},
+};
+
+// Fails like a pipe whose reader has gone away
+struct BrokenPipe;
+
+impl Write for BrokenPipe {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[test]
+fn failing_to_write_the_output_is_an_io_error() {
+    let mut vm = VM::with_output(BrokenPipe, Capture::default());
+
+    assert!(matches!(vm.interpret("print 1;"), Err(Error::Io)));
+}
+
+#[test]
+fn failing_to_write_diagnostics_is_an_io_error() {
+    let mut vm = VM::with_output(Capture::default(), BrokenPipe);
+
+    assert!(matches!(vm.interpret("print (;"), Err(Error::Io)));
+    assert!(matches!(vm.interpret("print -nil;"), Err(Error::Io)));
+
+    vm.set_debug_options(DebugOptions {
+        trace_execution: true,
+        ..DebugOptions::default()
+    });
+    assert!(matches!(vm.interpret("var a = 1;"), Err(Error::Io)));
+}
+
+// Interprets `print 1 + 2;` and returns what was written to the diagnostics
+fn debug_output(options: DebugOptions) -> String {
+    let out = Capture::default();
+    let diagnostics = Capture::default();
+    let mut vm = VM::with_output(out.clone(), diagnostics.clone());
+    vm.set_debug_options(options);
+
+    assert!(vm.interpret("print 1 + 2;").is_ok());
+    assert_eq!(out.contents(), "3\n", "Debug output leaked into the program output");
+    diagnostics.contents()
+}
+
+#[test]
+fn debug_output_goes_to_the_diagnostics() {
+    assert_eq!(debug_output(DebugOptions::default()), "");
+
+    let tokens = debug_output(DebugOptions {
+        print_tokens: true,
+        ..DebugOptions::default()
+    });
+    assert!(tokens.contains("   1 Ok(Plus) '+'"), "{tokens}");
+
+    let ast = debug_output(DebugOptions {
+        print_ast: true,
+        ..DebugOptions::default()
+    });
+    assert!(ast.starts_with("Ast {") && ast.contains("Binary("), "{ast}");
+
+    let disassembly = debug_output(DebugOptions {
+        print_disassembly: true,
+        ..DebugOptions::default()
+    });
+    assert!(disassembly.starts_with("== <script> =="), "{disassembly}");
+    assert!(disassembly.contains("| OP_ADD"), "{disassembly}");
+
+    let trace = debug_output(DebugOptions {
+        trace_execution: true,
+        ..DebugOptions::default()
+    });
+    assert!(trace.contains("[ <script> ][ 1 ][ 2 ]\nOP_ADD"), "{trace}");
+}